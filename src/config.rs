@@ -0,0 +1,98 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::zbcli::Signing;
+
+/// Where defaults are read from unless `--config` names another file
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/zb-installer.toml";
+
+/// Defaults for command line options, so every unit of a fleet is provisioned the same way.
+/// Options given on the command line take precedence.
+///
+/// ```toml
+/// signing = "hardware"
+/// rpi_model = "Pi5"
+/// channel = "stable"
+/// version = "~1.2"
+/// release_index = "https://mirror.example.com/zb"
+/// proxy = "http://proxy.example.com:3128"
+/// timeout = 60
+/// retries = 10
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The file the settings were read from
+    #[serde(skip)]
+    pub path: PathBuf,
+
+    pub signing: Option<Signing>,
+
+    /// MODELSPEC, as for `--rpi-model`
+    pub rpi_model: Option<String>,
+
+    /// As for `--channel`
+    pub channel: Option<String>,
+
+    /// `owner/name`, as for `--repo`
+    pub repo: Option<String>,
+
+    /// As for `--zb-version`
+    pub version: Option<String>,
+
+    /// Directory zbcli is installed to instead of `/usr/bin`
+    pub install_dir: Option<PathBuf>,
+
+    /// Release index URL, e.g. of a local mirror, as for `--release-index`
+    pub release_index: Option<String>,
+
+    /// Proxy URL for all requests
+    pub proxy: Option<String>,
+
+    /// As for `--timeout`, in seconds
+    pub timeout: Option<u32>,
+
+    /// As for `--retries`
+    pub retries: Option<u32>,
+}
+
+impl Config {
+    /// Reads `path`, or [`DEFAULT_CONFIG_PATH`] if there is one when no path is given
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_CONFIG_PATH), false),
+        };
+
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(e).context(format!("Failed to read config ({})", path.display())),
+        };
+
+        let mut config: Self =
+            toml::from_str(&content).context(format!("Invalid config ({})", path.display()))?;
+        config.path = path.to_path_buf();
+
+        Ok(config)
+    }
+
+    /// Parses `value`, one of the settings read from the file
+    pub fn setting<T>(
+        &self,
+        value: Option<&str>,
+        parse: impl FnOnce(&str) -> Result<T>,
+    ) -> Result<Option<T>> {
+        value.map(parse).transpose().context(format!(
+            "Invalid setting in config ({})",
+            self.path.display()
+        ))
+    }
+}
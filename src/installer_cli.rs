@@ -24,77 +24,630 @@
 // You may not use any Zymbit products in life-critical equipment unless authorized officers
 // of the parties have executed a special contract specifically governing such use.
 // -------------------------------------------------------------------------------------------------------
-use crate::system::PiModule;
-use anyhow::{bail, Result};
+use crate::{
+    config::{Config, DEFAULT_CONFIG_PATH},
+    system::{PiModule, MODELSPECS},
+    terminal::{self, OutputFormat},
+    toolchain::{
+        download::NetworkOptions,
+        install::LocalSource,
+        source::{self, Channel},
+        version::VersionSpec,
+    },
+    zbcli::Signing,
+};
+use anyhow::{bail, Context, Result};
+use derive_more::Display;
+use std::{path::PathBuf, time::Duration};
+
+#[derive(Display, Debug)]
+pub enum Command {
+    /// Download and install zbcli
+    #[display(fmt = "install")]
+    Install,
+
+    /// Show available releases and their assets
+    #[display(fmt = "list")]
+    List,
+
+    /// Remove the installed zbcli, and with `purge` the versions stored for rollback
+    #[display(fmt = "uninstall")]
+    Uninstall { purge: bool },
+
+    /// Print the detected system
+    #[display(fmt = "detect")]
+    Detect,
+
+    /// Check the installed zbcli against the copy stored when it was installed
+    #[display(fmt = "verify")]
+    Verify,
+
+    /// Install the newest zbcli if it is newer than the installed one
+    #[display(fmt = "update")]
+    Update,
+
+    /// Restore a stored zbcli, either the named tag or the one installed before the current one
+    #[display(fmt = "rollback")]
+    Rollback { tag: Option<String> },
+
+    /// Replace this installer with the newest published one
+    #[display(fmt = "self-update")]
+    SelfUpdate,
+}
+
+/// Options for choosing where releases are downloaded from, accepted by every command that does
+const SOURCE_OPTIONS: &[&str] = &[
+    "--channel",
+    "--repo",
+    "--release-index",
+    "--github-token",
+    "--retries",
+    "--timeout",
+];
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "install" => Command::Install,
+            "list" => Command::List,
+            "uninstall" => Command::Uninstall { purge: false },
+            "detect" => Command::Detect,
+            "verify" => Command::Verify,
+            "update" => Command::Update,
+            "rollback" => Command::Rollback { tag: None },
+            "self-update" => Command::SelfUpdate,
+            _ => return None,
+        })
+    }
+
+    fn all() -> [Self; 8] {
+        [
+            Command::Install,
+            Command::List,
+            Command::Uninstall { purge: false },
+            Command::Detect,
+            Command::Verify,
+            Command::Update,
+            Command::Rollback { tag: None },
+            Command::SelfUpdate,
+        ]
+    }
+
+    /// Whether the command reads releases from GitHub or a release index
+    #[must_use]
+    pub fn uses_release_source(&self) -> bool {
+        matches!(
+            self,
+            Command::Install | Command::Update | Command::List | Command::SelfUpdate
+        )
+    }
+
+    fn summary(&self) -> &'static str {
+        match self {
+            Command::Install => "Download, verify and install zbcli (the default command)",
+            Command::List => "List available releases and their assets",
+            Command::Uninstall { .. } => {
+                "Remove the installed zbcli. Stored versions are kept for rollback."
+            }
+            Command::Detect => "Print the detected operating system, Pi and Zymbit module",
+            Command::Verify => {
+                "Check the installed zbcli against the copy stored when it was installed"
+            }
+            Command::Update => {
+                "Install the newest release allowed by --zb-version if it is newer than the \
+                    installed zbcli. Exits with 0 after updating, 2 if already current and 1 on error."
+            }
+            Command::Rollback { .. } => {
+                "Restore the zbcli installed before the current one, or VERSION_TAG, from the \
+                    local version store. No network access is needed."
+            }
+            Command::SelfUpdate => {
+                "Replace this installer with the newest published one. Exits with 0 after \
+                    updating, 2 if already current and 1 on error."
+            }
+        }
+    }
+
+    fn usage(&self) -> String {
+        match self {
+            Command::Install => "zb-install [install] [OPTIONS]".to_string(),
+            Command::Rollback { .. } => "zb-install rollback [OPTIONS] [VERSION_TAG]".to_string(),
+            command => format!("zb-install {command} [OPTIONS]"),
+        }
+    }
+
+    /// Options the command accepts, besides `--help`. Every command takes `--config` and
+    /// `--output`.
+    fn options(&self) -> Vec<&'static str> {
+        let own: &[&str] = match self {
+            Command::Install => &[
+                "--with-hardware-signing",
+                "--with-software-signing",
+                "--zb-version",
+                "--include-prereleases",
+                "--rpi-model",
+                "--allow-unverified",
+                "--force",
+                "--from-file",
+                "--from-dir",
+                "--non-interactive",
+                "--yes",
+            ],
+            Command::Update => &[
+                "--with-hardware-signing",
+                "--with-software-signing",
+                "--zb-version",
+                "--include-prereleases",
+                "--rpi-model",
+                "--allow-unverified",
+                "--force",
+                "--non-interactive",
+                "--yes",
+            ],
+            Command::List => &["--zb-version", "--include-prereleases"],
+            Command::Uninstall { .. } => &["--purge"],
+            Command::Detect => &["--rpi-model"],
+            Command::SelfUpdate => &["--allow-unverified"],
+            Command::Verify | Command::Rollback { .. } => &[],
+        };
+
+        let mut options = own.to_vec();
+        if self.uses_release_source() {
+            // the installer is always published to its own repository, whatever the channel
+            let self_update = matches!(self, Command::SelfUpdate);
+            options.extend(
+                SOURCE_OPTIONS
+                    .iter()
+                    .filter(|option| !(self_update && matches!(**option, "--channel" | "--repo"))),
+            );
+        }
+        options.extend_from_slice(&["--config", "--output"]);
+        options
+    }
+
+    fn print_help(&self) {
+        println!("usage: {}", self.usage());
+        println!("{}", self.summary());
+
+        let options = self.options();
+        if !options.is_empty() {
+            println!("\nOptions:");
+            for option in options {
+                let (arg, text) = option_help(option);
+                if arg.is_empty() {
+                    println!("  {option}\n        {text}");
+                } else {
+                    println!("  {option} {arg}\n        {text}");
+                }
+            }
+        }
+    }
+}
+
+/// Argument placeholder and explanation for `option`
+fn option_help(option: &str) -> (&'static str, String) {
+    match option {
+        "--with-hardware-signing" => (
+            "",
+            "Include hardware key signing (requires SCM or HSM6)".into(),
+        ),
+        "--with-software-signing" => ("", "Use software signing only".into()),
+        "--zb-version" => (
+            "<latest|VERSION_TAG|CONSTRAINT>",
+            "Release to use. CONSTRAINT picks the newest matching version, e.g. '~1.2' or \
+                '>=1.1,<2'. A bare '1.2' means the newest 1.2.x."
+                .into(),
+        ),
+        "--include-prereleases" => (
+            "",
+            "Offer release candidates and other pre-releases, and let 'latest' and CONSTRAINT \
+                resolve to them"
+                .into(),
+        ),
+        "--rpi-model" => (
+            "<MODELSPEC>",
+            format!(
+                "Use this Pi model instead of the detected one. MODELSPEC is one of {}, in any \
+                    case and ignoring spaces, '-' and '_'",
+                MODELSPECS
+                    .iter()
+                    .map(|(alias, _)| *alias)
+                    .collect::<Vec<_>>()
+                    .join("|")
+            ),
+        ),
+        "--allow-unverified" => (
+            "",
+            "Install even when the release publishes no checksum or signature. A download that \
+                doesn't match them is never installed. Not recommended."
+                .into(),
+        ),
+        "--force" => (
+            "",
+            "Install the hardware signing variant even if no SCM or HSM6 is detected, or a \
+                --from-file asset not named for this system"
+                .into(),
+        ),
+        "--from-file" => (
+            "<PATH>",
+            "Install a local asset file without network access. Its checksum and signature are \
+                looked for beside it."
+                .into(),
+        ),
+        "--from-dir" => (
+            "<PATH>",
+            "Install the asset matching this system from a directory of release assets, without \
+                network access"
+                .into(),
+        ),
+        "--non-interactive" => (
+            "",
+            "Never prompt; fail if the signing or version isn't given. This is the default when \
+                there is no terminal."
+                .into(),
+        ),
+        "--yes" => (
+            "",
+            "Never prompt; use software signing and the latest release unless told otherwise"
+                .into(),
+        ),
+        "--channel" => (
+            "<stable|devel>",
+            format!(
+                "Zymbit release repository to use (default: {})",
+                Channel::default()
+            ),
+        ),
+        "--repo" => (
+            "<OWNER/NAME>",
+            "Use the releases of another GitHub repository, such as a fork".into(),
+        ),
+        "--release-index" => (
+            "<URL>",
+            "Read releases from URL/index.json on a plain HTTP server instead of GitHub".into(),
+        ),
+        "--github-token" => (
+            "<TOKEN>",
+            "Authenticate GitHub API requests, raising the rate limit for many devices behind \
+                one address. Defaults to $ZB_GITHUB_TOKEN, then \
+                $GITHUB_TOKEN."
+                .into(),
+        ),
+        "--retries" => (
+            "<N>",
+            format!(
+                "How often an interrupted download is retried (default: {}). Interrupted \
+                    downloads are also resumed on the next run.",
+                NetworkOptions::default().retries
+            ),
+        ),
+        "--timeout" => (
+            "<SECONDS>",
+            format!(
+                "How long to wait for the server (default: {})",
+                NetworkOptions::default().timeout.as_secs()
+            ),
+        ),
+        "--purge" => ("", "Also delete the versions stored for rollback".into()),
+        "--config" => (
+            "<PATH>",
+            format!(
+                "Read defaults for these options from PATH instead of {DEFAULT_CONFIG_PATH}. \
+                    Options given here take precedence."
+            ),
+        ),
+        "--output" => (
+            "<text|json>",
+            "Print the result as JSON on stdout for scripts (default: text). Progress and other \
+                messages go to stderr."
+                .into(),
+        ),
+        _ => ("", String::new()),
+    }
+}
+
+fn print_help() {
+    for (i, command) in Command::all().iter().enumerate() {
+        let prefix = if i == 0 { "usage:" } else { "      " };
+        println!("{prefix} {}", command.usage());
+    }
+    println!("       zb-install [COMMAND] [-h | --help]");
+
+    println!("\nCommands:");
+    for command in Command::all() {
+        println!("  {:<12} {}", command.to_string(), command.summary());
+    }
+    println!("\nRun 'zb-install COMMAND --help' for the options of a command.");
+    println!(
+        "\nOptions not given can be set in the environment instead: ZB_SIGNING, ZB_VERSION, \
+        ZB_RPI_MODEL,\nZB_INCLUDE_PRERELEASES, ZB_CHANNEL, ZB_REPO, ZB_RELEASE_INDEX, ZB_FROM_FILE, \
+        ZB_FROM_DIR,\nZB_GITHUB_TOKEN, ZB_RETRIES, ZB_TIMEOUT, ZB_PROXY, ZB_INSTALL_DIR, ZB_FORCE, \
+        ZB_YES,\nZB_NON_INTERACTIVE, ZB_OUTPUT and ZB_CONFIG. These take precedence over the config \
+        file\n({DEFAULT_CONFIG_PATH}). --allow-unverified can only be given on the command line."
+    );
+}
+
+/// What to do when a choice wasn't given on the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interaction {
+    /// Ask the user
+    Prompt,
+
+    /// Take the default: software signing, the latest release
+    UseDefaults,
+
+    /// Fail, naming the options that make the choice
+    Refuse,
+}
 
 #[derive(Debug)]
 pub struct InstallerArgs {
+    pub command: Command,
     pub use_hw: Option<bool>,
-    pub zb_version: Option<String>,
+    pub zb_version: Option<VersionSpec>,
     pub rpi_model: Option<PiModule>,
+    pub allow_unverified: bool,
+
+    /// Install what was asked for even if it doesn't suit the detected hardware
+    pub force: bool,
+    pub include_prereleases: bool,
+    pub local_source: Option<LocalSource>,
+    pub release_index: Option<String>,
+    pub channel: Channel,
+    pub repo: Option<String>,
+    pub github_token: Option<String>,
+    pub network: NetworkOptions,
+
+    /// Directory to install to instead of the default
+    pub install_dir: Option<PathBuf>,
+    pub interaction: Interaction,
+    pub output: OutputFormat,
 }
 
 pub fn parse_args() -> Result<InstallerArgs> {
     let mut use_hw = None;
     let mut zb_version = None;
-    let mut argv = std::env::args().into_iter();
+    let mut argv = std::env::args().skip(1).peekable(); // skip argv[0]
     let mut rpi_model = None;
-    argv.next(); // skip argv[0]
+    let mut allow_unverified = false;
+    let mut force = false;
+    let mut include_prereleases = false;
+    let mut non_interactive = false;
+    let mut assume_yes = false;
+    let mut local_source = None;
+    let mut release_index = None;
+    let mut channel = None;
+    let mut repo = None;
+    let mut github_token = None;
+    let mut retries = None;
+    let mut timeout = None;
+    let mut output = None;
+    let mut config_path = None;
+
+    // the command comes first; without one, we install
+    let named_command = argv.peek().and_then(|arg| Command::from_name(arg));
+    let explicit_command = named_command.is_some();
+    if explicit_command {
+        argv.next();
+    }
+    let mut command = named_command.unwrap_or(Command::Install);
 
     while let Some(arg) = argv.next() {
-        match arg.as_str() {
-            "-h" | "--help" => {
-                println!(
-                    "usage: zb-install [--with-hardware-signing | --with-software-signing] \
-                                            [--zb-version <latest|VERSION_TAG>] \
-                                             [--rpi-model <MODELSPEC>]"
-                );
-                println!("       zb-install [-h | --help]");
-                println!("MODELSPEC is a case-insensitive Pi platform identifier of the following form:\n \
-                        Pi4|Pi5|PiZero[2W]");
-                std::process::exit(0);
+        if arg == "-h" || arg == "--help" {
+            if explicit_command {
+                command.print_help();
+            } else {
+                print_help();
             }
+            std::process::exit(0);
+        }
+
+        if arg.starts_with('-') && !command.options().contains(&arg.as_str()) {
+            bail!(
+                "option '{arg}' is not valid for '{command}'. See 'zb-install {command} --help'."
+            );
+        }
 
+        match arg.as_str() {
             "--with-hardware-signing" => use_hw = Some(true),
             "--with-software-signing" => use_hw = Some(false),
+            "--allow-unverified" => allow_unverified = true,
+            "--force" => force = true,
+            "--include-prereleases" => include_prereleases = true,
+            "--non-interactive" => non_interactive = true,
+            "--yes" => assume_yes = true,
 
-            "--zb-version" => {
-                if let Some(val) = argv.next() {
-                    // make sure we don't treat the next CLI flag as our argument
-                    if !val.starts_with('-') {
-                        zb_version = Some(val);
-                        continue;
-                    }
+            "--purge" => {
+                if let Command::Uninstall { purge } = &mut command {
+                    *purge = true;
                 }
-                bail!("option '--zb-version' requires an argument");
             }
 
-            "--rpi-model" => {
-                if let Some(val) = argv.next() {
-                    // make sure we don't treat the next CLI flag as our argument
-                    if !val.starts_with('-') {
-                        rpi_model = Some(if val.contains('4') {
-                            PiModule::Rpi4_64
-                        } else if val.contains('5') {
-                            PiModule::Rpi5_64
-                        } else if val.contains("Zero") {
-                            PiModule::Rpi0_64
-                        } else {
-                            bail!("unknown MODELSPEC '{val}'")
-                        });
-                    }
-                    continue;
+            "--zb-version" => zb_version = Some(option_value(&arg, &mut argv)?.parse()?),
+            "--rpi-model" => rpi_model = Some(option_value(&arg, &mut argv)?.parse()?),
+
+            flag @ ("--from-file" | "--from-dir") => {
+                let path = PathBuf::from(option_value(flag, &mut argv)?);
+                if local_source.is_some() {
+                    bail!("options '--from-file' and '--from-dir' are mutually exclusive");
                 }
-                bail!("option '--rpi-model' requires an argument");
+                local_source = Some(if flag == "--from-file" {
+                    LocalSource::File(path)
+                } else {
+                    LocalSource::Dir(path)
+                });
+            }
+
+            flag @ ("--retries" | "--timeout") => {
+                let val = option_value(flag, &mut argv)?;
+                let Ok(n) = val.parse::<u32>() else {
+                    bail!("option '{flag}' requires a number, got '{val}'");
+                };
+                if flag == "--retries" {
+                    retries = Some(n);
+                } else {
+                    timeout = Some(n);
+                }
+            }
+
+            "--github-token" => github_token = Some(option_value(&arg, &mut argv)?),
+            "--channel" => channel = Some(option_value(&arg, &mut argv)?.parse()?),
+            "--repo" => {
+                let val = option_value(&arg, &mut argv)?;
+                source::parse_repo_spec(&val)?;
+                repo = Some(val);
             }
+            "--config" => config_path = Some(PathBuf::from(option_value(&arg, &mut argv)?)),
+            "--output" => output = Some(option_value(&arg, &mut argv)?.parse()?),
+            "--release-index" => release_index = Some(option_value(&arg, &mut argv)?),
 
-            _ => bail!("unexpected argument {}", arg),
+            _ => match &mut command {
+                Command::Rollback { tag: tag @ None } if !arg.starts_with('-') => *tag = Some(arg),
+                _ => bail!("unexpected argument {arg}"),
+            },
         }
     }
 
+    // what wasn't given on the command line comes from the environment, then the config file
+    let config_path = config_path.or_else(|| env_value("ZB_CONFIG").map(PathBuf::from));
+    let config = Config::load(config_path.as_deref())?;
+
+    let use_hw = match use_hw {
+        Some(use_hw) => Some(use_hw),
+        None => from_env("ZB_SIGNING", str::parse::<Signing>)?
+            .or(config.signing)
+            .map(|signing| signing == Signing::Hardware),
+    };
+    let zb_version = match zb_version {
+        Some(zb_version) => Some(zb_version),
+        None => match from_env("ZB_VERSION", str::parse)? {
+            Some(zb_version) => Some(zb_version),
+            None => config.setting(config.version.as_deref(), str::parse)?,
+        },
+    };
+    let rpi_model = match rpi_model {
+        Some(rpi_model) => Some(rpi_model),
+        None => match from_env("ZB_RPI_MODEL", str::parse)? {
+            Some(rpi_model) => Some(rpi_model),
+            None => config.setting(config.rpi_model.as_deref(), str::parse)?,
+        },
+    };
+    let force = force || from_env("ZB_FORCE", parse_switch)?.unwrap_or_default();
+    let include_prereleases = include_prereleases
+        || from_env("ZB_INCLUDE_PRERELEASES", parse_switch)?.unwrap_or_default();
+
+    // the release source options override each other, so they're taken from the environment or
+    // the config file only if none was given, and as a set
+    let source_vars = ["ZB_CHANNEL", "ZB_REPO", "ZB_RELEASE_INDEX"];
+    let parse_repo = |val: &str| -> Result<String> {
+        source::parse_repo_spec(val)?;
+        Ok(val.to_string())
+    };
+    if channel.is_none() && repo.is_none() && release_index.is_none() {
+        if source_vars.iter().any(|var| env_value(var).is_some()) {
+            channel = from_env("ZB_CHANNEL", str::parse)?;
+            repo = from_env("ZB_REPO", parse_repo)?;
+            release_index = env_value("ZB_RELEASE_INDEX");
+        } else {
+            channel = config.setting(config.channel.as_deref(), str::parse)?;
+            repo = config.setting(config.repo.as_deref(), parse_repo)?;
+            release_index = config.release_index;
+        }
+    }
+
+    // a local asset replaces the release source, so it only applies where it can be given
+    if local_source.is_none() && command.options().contains(&"--from-file") {
+        local_source = match (env_value("ZB_FROM_FILE"), env_value("ZB_FROM_DIR")) {
+            (Some(_), Some(_)) => {
+                bail!("environment variables ZB_FROM_FILE and ZB_FROM_DIR are mutually exclusive")
+            }
+            (Some(file), None) => Some(LocalSource::File(PathBuf::from(file))),
+            (None, Some(dir)) => Some(LocalSource::Dir(PathBuf::from(dir))),
+            (None, None) => None,
+        };
+    }
+    let github_token = github_token
+        .or_else(|| env_value("ZB_GITHUB_TOKEN"))
+        .or_else(|| env_value("GITHUB_TOKEN"));
+    let output = match output {
+        Some(output) => output,
+        None => from_env("ZB_OUTPUT", str::parse)?.unwrap_or(OutputFormat::Text),
+    };
+
+    let defaults = NetworkOptions::default();
+    let retries = retries.map_or_else(|| from_env("ZB_RETRIES", parse_count), |n| Ok(Some(n)))?;
+    let timeout = timeout.map_or_else(|| from_env("ZB_TIMEOUT", parse_count), |n| Ok(Some(n)))?;
+    let network = NetworkOptions {
+        retries: retries.or(config.retries).unwrap_or(defaults.retries),
+        timeout: timeout
+            .or(config.timeout)
+            .map_or(defaults.timeout, |secs| Duration::from_secs(secs.into())),
+        proxy: env_value("ZB_PROXY").or(config.proxy),
+    };
+    let interaction = if assume_yes || from_env("ZB_YES", parse_switch)?.unwrap_or_default() {
+        Interaction::UseDefaults
+    } else if non_interactive
+        || from_env("ZB_NON_INTERACTIVE", parse_switch)?.unwrap_or_default()
+        || !terminal::can_prompt()
+    {
+        Interaction::Refuse
+    } else {
+        Interaction::Prompt
+    };
+    let install_dir = env_value("ZB_INSTALL_DIR")
+        .map(PathBuf::from)
+        .or(config.install_dir);
+
     Ok(InstallerArgs {
+        command,
         use_hw,
         zb_version,
         rpi_model,
+        allow_unverified,
+        force,
+        include_prereleases,
+        local_source,
+        release_index,
+        channel: channel.unwrap_or_default(),
+        repo,
+        github_token,
+        network,
+        install_dir,
+        interaction,
+        output,
     })
 }
+
+/// Takes the value of `flag` from `argv`, refusing to take the next flag for it
+fn option_value(flag: &str, argv: &mut impl Iterator<Item = String>) -> Result<String> {
+    match argv.next() {
+        Some(val) if !val.starts_with('-') => Ok(val),
+        _ => bail!("option '{flag}' requires an argument"),
+    }
+}
+
+/// The value of the environment variable `var`, unless it is unset or empty
+fn env_value(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|val| !val.is_empty())
+}
+
+/// Parses the environment variable `var`, if it is set
+fn from_env<T>(var: &str, parse: impl FnOnce(&str) -> Result<T>) -> Result<Option<T>> {
+    env_value(var)
+        .map(|val| parse(&val).context(format!("Invalid environment variable {var}")))
+        .transpose()
+}
+
+/// Parses a boolean environment variable such as `ZB_INCLUDE_PRERELEASES=1`
+fn parse_switch(val: &str) -> Result<bool> {
+    match val.to_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => bail!("expected '1' or '0', got '{val}'"),
+    }
+}
+
+fn parse_count(val: &str) -> Result<u32> {
+    val.parse()
+        .context(format!("expected a number, got '{val}'"))
+}
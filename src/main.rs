@@ -31,13 +31,27 @@
 #![allow(clippy::struct_excessive_bools)]
 #![allow(clippy::enum_variant_names)]
 
-use std::process;
+use std::{process, time::Instant};
 
-use crate::system::PiModule;
-use anyhow::{Context, Result};
+use crate::{
+    installer_cli::{Command, InstallerArgs, Interaction},
+    system::{PiModule, ZymbitModule},
+    zbcli::Signing,
+};
+use anyhow::{bail, Context, Result};
 use dialoguer::theme::ColorfulTheme;
-use terminal::{formatted_left_output, OutputColor};
+use semver::VersionReq;
+use serde::Serialize;
+use serde_json::json;
+use terminal::{formatted_left_output, message, OutputColor};
+use toolchain::{
+    self_update,
+    source::{GithubSource, HttpIndexSource, ReleaseSource},
+    state::{self, InstallRecord, StateManifest},
+    version::{self, VersionSpec},
+};
 
+mod config;
 mod installer_cli;
 mod system;
 mod terminal;
@@ -46,11 +60,283 @@ mod zbcli;
 
 async fn start() -> Result<()> {
     let cli_args = installer_cli::parse_args()?;
+    terminal::set_output_format(cli_args.output);
+    if let Some(install_dir) = &cli_args.install_dir {
+        toolchain::install::set_install_dir(install_dir.clone());
+    }
+    let zb_path = toolchain::install::install_path("zbcli");
+
+    if cli_args.command.uses_release_source() && cli_args.local_source.is_none() {
+        self_update::warn_if_outdated(installer_source(&cli_args)?.as_ref(), &cli_args.network)
+            .await;
+    }
+
+    match &cli_args.command {
+        Command::Install => install(&cli_args, cli_args.zb_version.as_ref()).await,
+        Command::Update => {
+            if !update(&cli_args).await? {
+                // already current, which scripts need to tell apart from an update
+                process::exit(2);
+            }
+            Ok(())
+        }
+        Command::List => list(&cli_args).await,
+        Command::Uninstall { purge } => {
+            toolchain::install::uninstall("zbcli", *purge)?;
+            if terminal::json_output() {
+                terminal::print_json(&json!({
+                    "tool": "zbcli",
+                    "path": zb_path,
+                    "purged": purge,
+                }))?;
+            }
+            Ok(())
+        }
+        Command::Detect => {
+            let system = system::System::get(cli_args.rpi_model)?;
+            if terminal::json_output() {
+                terminal::print_json(&system)
+            } else {
+                println!("{system}");
+                Ok(())
+            }
+        }
+        Command::Verify => {
+            let tag = toolchain::install::verify_installed("zbcli")?;
+            if terminal::json_output() {
+                terminal::print_json(&json!({
+                    "tool": "zbcli",
+                    "version": tag,
+                    "path": zb_path,
+                    "sha256": toolchain::verify::sha256_file(&zb_path)?,
+                    "verified": true,
+                }))
+            } else {
+                println!(
+                    "{} {} is {tag}, unchanged since it was installed",
+                    formatted_left_output("Verified", &OutputColor::Green),
+                    zb_path.display()
+                );
+                Ok(())
+            }
+        }
+        Command::SelfUpdate => {
+            let result = self_update::self_update(
+                installer_source(&cli_args)?.as_ref(),
+                &cli_args.network,
+                cli_args.allow_unverified,
+            )
+            .await?;
+            let published = result
+                .to
+                .as_ref()
+                .map_or_else(|| "the published version".to_string(), ToString::to_string);
+
+            if terminal::json_output() {
+                terminal::print_json(&json!({
+                    "tool": "zb-installer",
+                    "installed": result.from.to_string(),
+                    "latest": result.to.as_ref().map(ToString::to_string),
+                    "updated": result.updated,
+                }))?;
+            } else if result.updated {
+                println!(
+                    "{} zb-installer {} to {published}",
+                    formatted_left_output("Updated", &OutputColor::Green),
+                    result.from
+                );
+            } else {
+                println!("zb-installer {} is already current", result.from);
+            }
+
+            if !result.updated {
+                process::exit(2);
+            }
+            Ok(())
+        }
+        Command::Rollback { tag } => {
+            let store = toolchain::store::VersionStore::open();
+            let restored = store.rollback("zbcli", tag.as_deref(), &zb_path)?;
+            let channel = store.channel(&restored);
+            if let Err(e) = state::record_rollback("zbcli", &store.binary_path("zbcli", &restored))
+            {
+                eprintln!(
+                    "{} failed to update the install state: {e:#}",
+                    formatted_left_output("Warning", &OutputColor::Yellow)
+                );
+            }
+            if terminal::json_output() {
+                terminal::print_json(&json!({
+                    "tool": "zbcli",
+                    "version": restored,
+                    "channel": channel,
+                    "path": zb_path,
+                }))
+            } else {
+                println!(
+                    "{} zbcli {restored} ({})",
+                    formatted_left_output("Restored", &OutputColor::Green),
+                    channel.as_deref().unwrap_or("unknown")
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Result of `install` and `update` for `--output json`
+#[derive(Serialize)]
+struct InstallReport<'a> {
+    system: &'a system::System,
+
+    #[serde(flatten)]
+    installed: InstallRecord,
+
+    elapsed_secs: f64,
+}
+
+/// Installs the `zb_version` release of zbcli for this system, prompting for anything not given
+async fn install(cli_args: &InstallerArgs, zb_version: Option<&VersionSpec>) -> Result<()> {
+    let started = Instant::now();
     let system = system::System::get(cli_args.rpi_model)?;
-    println!("{system}");
+    message!("{system}");
+    let version_needed = zb_version.is_none() && cli_args.local_source.is_none();
+    require_choices(cli_args, None, version_needed)?;
+    let target_asset = target_asset(cli_args, &system, None)?;
+
+    let latest = VersionSpec::Latest;
+    let zb_version = match cli_args.interaction {
+        Interaction::UseDefaults if version_needed => {
+            message!("No version given; installing the latest release (--yes)");
+            Some(&latest)
+        }
+        _ => zb_version,
+    };
+
+    install_asset(cli_args, started, &system, &target_asset, zb_version).await
+}
+
+/// Fails when there's no one to ask for choices that weren't given, naming the options that
+/// make them. `signing` is the signing to keep, if known.
+fn require_choices(
+    cli_args: &InstallerArgs,
+    signing: Option<Signing>,
+    version_needed: bool,
+) -> Result<()> {
+    if cli_args.interaction != Interaction::Refuse {
+        return Ok(());
+    }
+
+    let mut missing = Vec::new();
+    if cli_args.use_hw.is_none() && signing.is_none() {
+        missing.push("--with-software-signing or --with-hardware-signing");
+    }
+    if version_needed {
+        missing.push("--zb-version <latest|VERSION_TAG|CONSTRAINT>");
+    }
+    if !missing.is_empty() {
+        bail!(
+            "cannot prompt without a terminal (or with --non-interactive). Pass {}, or --yes to \
+            use software signing and the latest release.",
+            missing.join(" and ")
+        );
+    }
 
+    Ok(())
+}
+
+/// Installs the newest release of zbcli if it is newer than the installed one, keeping the
+/// installed signing variant unless told otherwise. Returns whether anything was installed.
+async fn update(cli_args: &InstallerArgs) -> Result<bool> {
+    let started = Instant::now();
+    let system = system::System::get(cli_args.rpi_model)?;
+    message!("{system}");
+    let manifest = StateManifest::load()?;
+    let signing = manifest.get("zbcli").map(|record| record.signing);
+    require_choices(cli_args, signing, false)?;
+    let target_asset = target_asset(cli_args, &system, signing)?;
+    let asset_name = target_asset.to_string();
+
+    // a configured constraint keeps a pinned device on its version line
+    let (spec, newest_in) = match &cli_args.zb_version {
+        None | Some(VersionSpec::Latest) => (
+            VersionSpec::Constraint(VersionReq::STAR),
+            "the newest release".to_string(),
+        ),
+        Some(spec) => (
+            spec.clone(),
+            format!("the newest release matching '{spec}'"),
+        ),
+    };
+    let source = release_source(cli_args)?;
+    let releases = version::list(
+        source.as_ref(),
+        "zbcli",
+        Some(&spec),
+        10_u8,
+        cli_args.include_prereleases,
+    )
+    .await?;
+    let Some((newest, newest_version)) = releases.iter().find_map(|release| {
+        release.asset(&asset_name)?;
+        Some((release, version::tag_version(&release.tag, "zbcli")?))
+    }) else {
+        bail!(
+            "no release from {} has asset '{asset_name}'",
+            source.describe()
+        );
+    };
+
+    match toolchain::install::installed_version("zbcli", &manifest) {
+        Some(installed) if installed >= newest_version => {
+            message!(
+                "zbcli {installed} is already installed; {newest_in} is {}",
+                newest.tag
+            );
+            if terminal::json_output() {
+                terminal::print_json(&json!({
+                    "tool": "zbcli",
+                    "installed": installed.to_string(),
+                    "latest": newest.tag,
+                    "updated": false,
+                }))?;
+            }
+            return Ok(false);
+        }
+        Some(installed) => message!("Updating zbcli {installed} to {}", newest.tag),
+        None => message!(
+            "The installed zbcli version is unknown; installing {}",
+            newest.tag
+        ),
+    }
+
+    install_asset(
+        cli_args,
+        started,
+        &system,
+        &target_asset,
+        Some(&VersionSpec::Tag(newest.tag.clone())),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// The zbcli asset for `system`. Signing is taken from the command line, else from `signing`,
+/// else asked for.
+fn target_asset(
+    cli_args: &InstallerArgs,
+    system: &system::System,
+    signing: Option<Signing>,
+) -> Result<zbcli::ZbcliAsset> {
+    let module_signs = system.zymbit_module.supports_hardware_signing();
     let should_use_hardware = match cli_args.use_hw {
         Some(flag) => flag,
+        None if signing.is_some() => signing == Some(Signing::Hardware),
+        None if cli_args.interaction != Interaction::Prompt => {
+            message!("No signing given; using software signing (--yes)");
+            false
+        }
         None => dialoguer::Select::with_theme(&ColorfulTheme::default())
             .with_prompt(
                 "'zbcli' comes with software signing by default. Include hardware key signing? \
@@ -58,12 +344,39 @@ async fn start() -> Result<()> {
             )
             .item("Yes")
             .item("No")
-            .default(1)
+            // "Yes" when the module can sign, else "No"
+            .default(usize::from(module_signs != Some(true)))
             .interact()
             .context("Failed to get signing option")?
             == 0,
     };
 
+    // the hardware variant only works with a module that holds a signing key
+    if should_use_hardware {
+        let module = &system.zymbit_module;
+        match module_signs {
+            Some(true) => {}
+            Some(false) if !cli_args.force => bail!(
+                "hardware signing requires an SCM or HSM6, but the Zymbit module is: {module}. \
+                Use --with-software-signing, or --force to install the hardware variant anyway."
+            ),
+            Some(false) => eprintln!(
+                "{} hardware signing requires an SCM or HSM6, but the Zymbit module is: {module}; \
+                installing the hardware variant anyway (--force)",
+                formatted_left_output("Warning", &OutputColor::Yellow)
+            ),
+            None if *module == ZymbitModule::Absent => eprintln!(
+                "{} no Zymbit module was detected, but hardware signing requires an SCM or HSM6",
+                formatted_left_output("Warning", &OutputColor::Yellow)
+            ),
+            None => eprintln!(
+                "{} could not tell whether the Zymbit module is an SCM or HSM6, which hardware \
+                signing requires",
+                formatted_left_output("Warning", &OutputColor::Yellow)
+            ),
+        }
+    }
+
     let target_asset = match system.pi_module {
         PiModule::Rpi0_64 => {
             if should_use_hardware {
@@ -88,16 +401,156 @@ async fn start() -> Result<()> {
         }
     };
 
-    toolchain::install::prompt("zbcli", &target_asset.to_string(), &cli_args.zb_version).await?;
+    Ok(target_asset)
+}
+
+/// Installs the `target_asset` build of zbcli and records it. `started` is when the command
+/// started, for the reported timing.
+async fn install_asset(
+    cli_args: &InstallerArgs,
+    started: Instant,
+    system: &system::System,
+    target_asset: &zbcli::ZbcliAsset,
+    zb_version: Option<&VersionSpec>,
+) -> Result<()> {
+    let installed = if let Some(local_source) = &cli_args.local_source {
+        toolchain::install::from_local(
+            "zbcli",
+            &target_asset.to_string(),
+            local_source,
+            zb_version,
+            cli_args.allow_unverified,
+            cli_args.force,
+        )
+        .await?
+    } else {
+        let source = release_source(cli_args)?;
+        toolchain::install::prompt(
+            source.as_ref(),
+            &cli_args.network,
+            "zbcli",
+            &target_asset.to_string(),
+            zb_version,
+            cli_args.include_prereleases,
+            cli_args.allow_unverified,
+        )
+        .await?
+    };
+
+    let installed = InstallRecord::new(installed, target_asset.signing());
+    let stored_copy =
+        toolchain::store::VersionStore::open().binary_path("zbcli", &installed.installed.version);
+    if let Err(e) = state::record_install(&installed, &stored_copy) {
+        eprintln!(
+            "{} failed to record the install in {}: {e:#}",
+            formatted_left_output("Warning", &OutputColor::Yellow),
+            StateManifest::path().display()
+        );
+    }
 
-    println!(
+    message!(
         "Installed zbcli. Run 'zbcli install' to install Bootware onto \
     your system or 'zbcli --help' for more options."
     );
 
+    if terminal::json_output() {
+        terminal::print_json(&InstallReport {
+            system,
+            installed,
+            elapsed_secs: started.elapsed().as_secs_f64(),
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Prints releases of zbcli with their assets, marking the installed one
+async fn list(cli_args: &InstallerArgs) -> Result<()> {
+    let source = release_source(cli_args)?;
+    let releases = version::list(
+        source.as_ref(),
+        "zbcli",
+        cli_args.zb_version.as_ref(),
+        10_u8,
+        cli_args.include_prereleases,
+    )
+    .await?;
+    let installed = toolchain::store::VersionStore::open()
+        .current("zbcli")
+        .unwrap_or_default();
+
+    if terminal::json_output() {
+        let releases = releases
+            .iter()
+            .map(|release| {
+                json!({
+                    "tag": release.tag,
+                    "prerelease": version::is_prerelease(release, "zbcli"),
+                    "installed": installed.as_ref() == Some(&release.tag),
+                    "assets": release.assets.iter().map(|asset| &asset.name).collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>();
+        return terminal::print_json(&json!({
+            "source": source.describe(),
+            "releases": releases,
+        }));
+    }
+
+    println!("Releases from {}:", source.describe());
+    for release in &releases {
+        let mut notes = Vec::new();
+        if version::is_prerelease(release, "zbcli") {
+            notes.push("pre-release");
+        }
+        if installed.as_ref() == Some(&release.tag) {
+            notes.push("installed");
+        }
+        if notes.is_empty() {
+            println!("{}", release.tag);
+        } else {
+            println!("{} ({})", release.tag, notes.join(", "));
+        }
+
+        for asset in &release.assets {
+            println!("    {}", asset.name);
+        }
+    }
+
     Ok(())
 }
 
+/// Where to look for releases: a release index if given, else a GitHub repository or channel
+fn release_source(cli_args: &InstallerArgs) -> Result<Box<dyn ReleaseSource>> {
+    let network = &cli_args.network;
+
+    Ok(match (&cli_args.release_index, &cli_args.repo) {
+        (Some(url), _) => Box::new(HttpIndexSource::new(url, network)?),
+        (None, Some(repo)) => Box::new(
+            GithubSource::from_spec(repo, network)?.with_token(cli_args.github_token.clone()),
+        ),
+        (None, None) => Box::new(
+            GithubSource::for_channel(cli_args.channel, network)?
+                .with_token(cli_args.github_token.clone()),
+        ),
+    })
+}
+
+/// Where the installer itself is published: the release index when one is given, as a mirror
+/// serves everything, else the installer's own repository regardless of `--channel` and `--repo`
+fn installer_source(cli_args: &InstallerArgs) -> Result<Box<dyn ReleaseSource>> {
+    let network = &cli_args.network;
+
+    if let Some(url) = &cli_args.release_index {
+        return Ok(Box::new(HttpIndexSource::new(url, network)?));
+    }
+    let (owner, repo) = self_update::INSTALLER_REPO;
+
+    Ok(Box::new(
+        GithubSource::new(owner, repo, network)?.with_token(cli_args.github_token.clone()),
+    ))
+}
+
 #[tokio::main]
 async fn main() {
     let _ = start().await.map_err(|e| {
@@ -106,6 +559,9 @@ async fn main() {
             formatted_left_output("Error", &OutputColor::Red),
             e
         );
+        if terminal::json_output() {
+            let _ = terminal::print_json(&json!({ "error": format!("{e:#}") }));
+        }
 
         process::exit(1);
     });
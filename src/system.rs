@@ -27,11 +27,17 @@
 
 use anyhow::{bail, Context, Result};
 use derive_more::Display;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::terminal::{formatted_left_output, OutputColor};
 use std::fmt::Display;
+use std::str::FromStr;
 use std::{
-    fs::{self},
-    os::unix::fs::PermissionsExt,
-    path::PathBuf,
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 static ROOTDEV_NAME: &str = "mmcblk0";
@@ -39,23 +45,107 @@ static ROOTDEV_NAME: &str = "mmcblk0";
 pub struct System {
     pub os: OperatingSystem,
     pub pi_module: PiModule,
+
+    /// The board, if its revision code could be read
+    pub board: Option<BoardRevision>,
     pub zymbit_module: ZymbitModule,
     // pub disk_layout: DiskLayout,
 }
 
-#[derive(Display, PartialEq)]
-pub enum OperatingSystem {
-    #[display(fmt = "Ubuntu")]
+/// A supported OS release, as identified by `/etc/os-release`
+#[derive(Debug, PartialEq)]
+pub struct OperatingSystem {
+    pub release: OsRelease,
+    pub support: &'static SupportedOs,
+}
+
+/// The fields of `/etc/os-release` the installer uses
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OsRelease {
+    /// e.g. `debian`, `ubuntu`
+    pub id: String,
+
+    /// Distributions this one is derived from, closest first
+    pub id_like: Vec<String>,
+
+    /// e.g. `12`, `24.04`
+    pub version_id: Option<String>,
+
+    /// e.g. `bookworm`, `noble`
+    pub version_codename: Option<String>,
+    pub pretty_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OsFamily {
+    /// Raspberry Pi OS, reported as Debian
+    RaspberryPiOs,
     Ubuntu,
+}
+
+/// An entry of [`SUPPORT_MATRIX`]
+#[derive(Debug, PartialEq)]
+pub struct SupportedOs {
+    /// Identifies the release in JSON output
+    pub key: &'static str,
+    pub name: &'static str,
+    pub family: OsFamily,
+
+    /// `ID` of the distribution, also matched against `ID_LIKE`
+    pub id: &'static str,
+
+    /// `VERSION_ID` of the release
+    pub version_id: &'static str,
 
-    #[display(fmt = "Rpi-Bullseye")]
-    RpiBullseye,
+    /// `VERSION_CODENAME` of the release, which identifies it where `VERSION_ID` is unset
+    pub codename: &'static str,
 
-    #[display(fmt = "Rpi-Bookworm")]
-    RpiBookworm,
+    /// The Pi models the installer supports on this release
+    pub pi_modules: &'static [PiModule],
 }
 
-#[derive(Display, PartialEq, Debug)]
+/// The OS releases the installer supports, and on which Pi models
+pub static SUPPORT_MATRIX: &[SupportedOs] = &[
+    SupportedOs {
+        key: "rpi_bullseye",
+        name: "Rpi-Bullseye",
+        family: OsFamily::RaspberryPiOs,
+        id: "debian",
+        version_id: "11",
+        codename: "bullseye",
+        pi_modules: &[PiModule::Rpi0_64, PiModule::Rpi4_64],
+    },
+    SupportedOs {
+        key: "rpi_bookworm",
+        name: "Rpi-Bookworm",
+        family: OsFamily::RaspberryPiOs,
+        id: "debian",
+        version_id: "12",
+        codename: "bookworm",
+        pi_modules: &[PiModule::Rpi0_64, PiModule::Rpi4_64, PiModule::Rpi5_64],
+    },
+    SupportedOs {
+        key: "ubuntu_22_04",
+        name: "Ubuntu 22.04",
+        family: OsFamily::Ubuntu,
+        id: "ubuntu",
+        version_id: "22.04",
+        codename: "jammy",
+        pi_modules: &[PiModule::Rpi0_64, PiModule::Rpi4_64],
+    },
+    SupportedOs {
+        key: "ubuntu_24_04",
+        name: "Ubuntu 24.04",
+        family: OsFamily::Ubuntu,
+        id: "ubuntu",
+        version_id: "24.04",
+        codename: "noble",
+        pi_modules: &[PiModule::Rpi0_64, PiModule::Rpi4_64, PiModule::Rpi5_64],
+    },
+];
+
+#[derive(Display, PartialEq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PiModule {
     /// Pi Zero 2 W
     #[display(fmt = "Raspberry Pi Zero 2 W")]
@@ -70,15 +160,101 @@ pub enum PiModule {
     Rpi5_64,
 }
 
-#[derive(Display, PartialEq)]
+/// MODELSPECs, as given to `--rpi-model`. They are matched ignoring case, spaces, `-` and `_`, so
+/// `Pi 4` and `pi-zero-2w` work too.
+pub static MODELSPECS: &[(&str, PiModule)] = &[
+    ("pi4", PiModule::Rpi4_64),
+    ("pi4b", PiModule::Rpi4_64),
+    ("pi400", PiModule::Rpi4_64),
+    ("cm4", PiModule::Rpi4_64),
+    ("cm4s", PiModule::Rpi4_64),
+    ("pi5", PiModule::Rpi5_64),
+    ("pi500", PiModule::Rpi5_64),
+    ("cm5", PiModule::Rpi5_64),
+    ("cm5lite", PiModule::Rpi5_64),
+    ("zero2w", PiModule::Rpi0_64),
+    ("pizero2w", PiModule::Rpi0_64),
+];
+
+/// Names of the original Pi Zero, which is easily given for a Zero 2 W but has no build
+static PI_ZERO_SPECS: &[&str] = &["zero", "zerow", "pizero", "pizerow"];
+
+/// A board as described by its new-style revision code, e.g. `c03114`
+#[derive(Debug, Clone, Serialize)]
+pub struct BoardRevision {
+    /// The revision code, in hex
+    pub code: String,
+    pub model: String,
+    pub memory_mb: u32,
+    pub manufacturer: String,
+
+    /// PCB revision, e.g. `1.4`
+    pub revision: String,
+
+    /// The installer's build for the board, if there is one
+    #[serde(skip)]
+    pub pi_module: Option<PiModule>,
+}
+
+/// Board types of the revision code, by their `TTTTTTTT` bits, and the installer's build for
+/// each supported one
+static BOARD_TYPES: &[(u32, &str, Option<PiModule>)] = &[
+    (0x00, "Raspberry Pi Model A", None),
+    (0x01, "Raspberry Pi Model B", None),
+    (0x02, "Raspberry Pi Model A+", None),
+    (0x03, "Raspberry Pi Model B+", None),
+    (0x04, "Raspberry Pi 2 Model B", None),
+    (0x06, "Compute Module 1", None),
+    (0x08, "Raspberry Pi 3 Model B", None),
+    (0x09, "Raspberry Pi Zero", None),
+    (0x0a, "Compute Module 3", None),
+    (0x0c, "Raspberry Pi Zero W", None),
+    (0x0d, "Raspberry Pi 3 Model B+", None),
+    (0x0e, "Raspberry Pi 3 Model A+", None),
+    (0x10, "Compute Module 3+", None),
+    (0x11, "Raspberry Pi 4 Model B", Some(PiModule::Rpi4_64)),
+    (0x12, "Raspberry Pi Zero 2 W", Some(PiModule::Rpi0_64)),
+    (0x13, "Raspberry Pi 400", Some(PiModule::Rpi4_64)),
+    (0x14, "Compute Module 4", Some(PiModule::Rpi4_64)),
+    (0x15, "Compute Module 4S", Some(PiModule::Rpi4_64)),
+    (0x17, "Raspberry Pi 5", Some(PiModule::Rpi5_64)),
+    (0x18, "Compute Module 5", Some(PiModule::Rpi5_64)),
+    (0x19, "Raspberry Pi 500", Some(PiModule::Rpi5_64)),
+    (0x1a, "Compute Module 5 Lite", Some(PiModule::Rpi5_64)),
+];
+
+/// Manufacturers of the revision code, by their `CCCC` bits
+static MANUFACTURERS: &[&str] = &[
+    "Sony UK",
+    "Egoman",
+    "Embest",
+    "Sony Japan",
+    "Embest",
+    "Stadium",
+];
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ZymbitModule {
-    #[display(fmt = "Zymkey")]
-    Zymkey,
+    #[display(fmt = "Zymkey 4i")]
+    Zymkey4i,
+
+    #[display(fmt = "Hardware Security Module 4")]
+    Hsm4,
+
+    #[display(fmt = "Hardware Security Module 6")]
+    Hsm6,
 
     #[display(fmt = "Secure Compute Module")]
     Scm,
-    // #[display(fmt = "Hardware Security Module 6")]
-    // HSM6,
+
+    /// A module is there, but nothing says which one
+    #[display(fmt = "Unidentified Zymbit module")]
+    Unidentified,
+
+    #[display(fmt = "None")]
+    #[serde(rename = "none")]
+    Absent,
 }
 
 #[allow(dead_code)]
@@ -91,26 +267,48 @@ pub struct DiskLayout {
     pub boot_mountpoint: PathBuf,
 }
 
-#[derive(Display)]
+#[derive(Display, Serialize)]
 pub enum Kernel {
     #[display(fmt = "vmlinuz")]
+    #[serde(rename = "vmlinuz")]
     Vmlinuz,
 
     #[display(fmt = "kernel8.img")]
+    #[serde(rename = "kernel8.img")]
     Kernel8img,
 
     #[display(fmt = "kernel_2712.img")]
+    #[serde(rename = "kernel_2712.img")]
     Kernel2712img,
 }
 
 impl System {
     pub fn get(pi_mod_override: Option<PiModule>) -> Result<Self> {
+        let os = OperatingSystem::get()?;
+        let board = BoardRevision::detect(Path::new("/"));
+        // use override passed on CLI if present, else try to autodetect, else return autodetection error
+        let pi_module = match pi_mod_override {
+            Some(pi_module) => {
+                if let Some(detected) = PiModule::get(board.as_ref())
+                    .ok()
+                    .filter(|detected| *detected != pi_module)
+                {
+                    eprintln!(
+                        "{} the Pi model given ({pi_module}) differs from the detected one \
+                        ({detected}); using {pi_module}",
+                        formatted_left_output("Warning", &OutputColor::Yellow)
+                    );
+                }
+                pi_module
+            }
+            None => PiModule::get(board.as_ref())?,
+        };
+        os.check_supports(pi_module)?;
+
         Ok(Self {
-            os: OperatingSystem::get()?,
-            // use override passed on CLI if present, else try to autodetect, else return autodetection error
-            pi_module: pi_mod_override
-                .map(|pm| Ok(pm))
-                .unwrap_or_else(|| PiModule::get())?,
+            os,
+            pi_module,
+            board,
             zymbit_module: ZymbitModule::get()?,
             // disk_layout: DiskLayout::get()?,
         })
@@ -118,7 +316,7 @@ impl System {
 
     #[must_use]
     pub fn kernel(&self) -> Kernel {
-        if self.os == OperatingSystem::Ubuntu {
+        if self.os.support.family == OsFamily::Ubuntu {
             return Kernel::Vmlinuz;
         }
 
@@ -131,10 +329,15 @@ impl System {
 
 impl Display for System {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let board = self
+            .board
+            .as_ref()
+            .map_or_else(|| "Unknown".to_string(), ToString::to_string);
         write!(
             f,
             "\t---------\n\
             \tPi Module:         {}\n\
+            \tBoard:             {board}\n\
             \tOperating System:  {}\n\
             \tZymbit module:     {}\n\
             \tKernel:            {}\n\
@@ -147,26 +350,193 @@ impl Display for System {
     }
 }
 
+impl Serialize for System {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut system = serializer.serialize_struct("System", 5)?;
+        system.serialize_field("pi_module", &self.pi_module)?;
+        system.serialize_field("board", &self.board)?;
+        system.serialize_field("os", &self.os)?;
+        system.serialize_field("zymbit_module", &self.zymbit_module)?;
+        system.serialize_field("kernel", &self.kernel())?;
+        system.end()
+    }
+}
+
 impl OperatingSystem {
     fn get() -> Result<Self> {
-        use OperatingSystem::{RpiBookworm, RpiBullseye, Ubuntu};
-        let os_rel = os_release()?;
-        if os_rel.contains("Ubuntu") {
-            Ok(Ubuntu)
-        } else if os_rel.contains("bookworm") {
-            Ok(RpiBookworm)
-        } else if os_rel.contains("bullseye") {
-            Ok(RpiBullseye)
+        Self::identify(OsRelease::get()?)
+    }
+
+    /// Looks `release` up in [`SUPPORT_MATRIX`], preferring entries for its own `ID` over those
+    /// for a distribution it is derived from
+    pub fn identify(release: OsRelease) -> Result<Self> {
+        let matches_version = |support: &&SupportedOs| {
+            release.version_id.as_deref() == Some(support.version_id)
+                || release.version_codename.as_deref() == Some(support.codename)
+        };
+        let support = SUPPORT_MATRIX
+            .iter()
+            .filter(matches_version)
+            .find(|support| support.id == release.id)
+            .or_else(|| {
+                release.id_like.iter().find_map(|id_like| {
+                    SUPPORT_MATRIX
+                        .iter()
+                        .filter(matches_version)
+                        .find(|support| support.id == id_like)
+                })
+            });
+
+        let Some(support) = support else {
+            let supported = SUPPORT_MATRIX
+                .iter()
+                .map(|support| support.name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!(
+                "Unsupported OS: {release}. Only these official RPi Debian and Ubuntu Linux \
+                releases are supported: {supported}."
+            )
+        };
+
+        Ok(Self { release, support })
+    }
+
+    /// Fails unless the installer supports `pi_module` on this release
+    pub fn check_supports(&self, pi_module: PiModule) -> Result<()> {
+        if self.support.pi_modules.contains(&pi_module) {
+            return Ok(());
+        }
+
+        let supported_on = SUPPORT_MATRIX
+            .iter()
+            .filter(|support| support.pi_modules.contains(&pi_module))
+            .map(|support| support.name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        bail!("{pi_module} is not supported on {self}. It is supported on: {supported_on}.")
+    }
+}
+
+impl Display for OperatingSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.support.name)
+    }
+}
+
+impl Serialize for OperatingSystem {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.support.key)
+    }
+}
+
+impl OsRelease {
+    /// Reads `/etc/os-release`, or `/usr/lib/os-release` where that is missing
+    fn get() -> Result<Self> {
+        let content = fs::read_to_string("/etc/os-release")
+            .or_else(|_| fs::read_to_string("/usr/lib/os-release"))
+            .context("unable to determine OS type")?;
+
+        Ok(Self::parse(&content))
+    }
+
+    /// Parses the `KEY=value` lines of an os-release file. Values may be quoted, and double-quoted
+    /// ones may contain backslash escapes.
+    #[must_use]
+    pub fn parse(content: &str) -> Self {
+        let mut release = Self::default();
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let value = unquote(value.trim());
+            match key.trim() {
+                "ID" => release.id = value,
+                "ID_LIKE" => release.id_like = value.split_whitespace().map(String::from).collect(),
+                "VERSION_ID" => release.version_id = Some(value),
+                "VERSION_CODENAME" => release.version_codename = Some(value),
+                "PRETTY_NAME" => release.pretty_name = Some(value),
+                _ => {}
+            }
+        }
+
+        // as the os-release specification says
+        if release.id.is_empty() {
+            release.id = "linux".to_string();
+        }
+
+        release
+    }
+}
+
+impl Display for OsRelease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(pretty_name) = &self.pretty_name {
+            write!(f, "{pretty_name} ")?;
+        }
+        write!(f, "(ID={}", self.id)?;
+        if let Some(version_id) = &self.version_id {
+            write!(f, ", VERSION_ID={version_id}")?;
+        }
+        if let Some(codename) = &self.version_codename {
+            write!(f, ", VERSION_CODENAME={codename}")?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Strips the quotes from an os-release value
+fn unquote(value: &str) -> String {
+    let quoted = value.len() >= 2
+        && (value.starts_with('"') && value.ends_with('"')
+            || value.starts_with('\'') && value.ends_with('\''));
+    if !quoted {
+        return value.to_string();
+    }
+
+    let inner = &value[1..value.len() - 1];
+    if value.starts_with('\'') {
+        return inner.to_string();
+    }
+
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                unquoted.push(escaped);
+            }
         } else {
-            bail!("Unsupported OS platform. Only the official RPi Debian and Ubuntu Linux releases are supported.")
+            unquoted.push(c);
         }
     }
+
+    unquoted
 }
 
 impl PiModule {
-    fn get() -> Result<Self> {
+    /// The build for `board`, or, without its revision code, for the model named in the
+    /// devicetree
+    fn get(board: Option<&BoardRevision>) -> Result<Self> {
         use PiModule::{Rpi0_64, Rpi4_64, Rpi5_64};
 
+        if let Some(board) = board {
+            let Some(pi_module) = board.pi_module else {
+                let supported = BOARD_TYPES
+                    .iter()
+                    .filter(|(_, _, pi_module)| pi_module.is_some())
+                    .map(|(_, model, _)| *model)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                bail!("{board} is not supported. Supported boards: {supported}.");
+            };
+            return Ok(pi_module);
+        }
+
         let model = fs::read_to_string("/sys/firmware/devicetree/base/model")
             .context("unable to retrieve host platform information from devicetree. (Hint: set the `--rpi-model` flag)")?;
 
@@ -179,26 +549,237 @@ impl PiModule {
                 Rpi0_64
             } else {
                 bail!(
-                    "Unknown host platform in devicetree: '{}'. (Hint: set the `--rpi-model` flag)",
-                    model
+                    "Unknown host platform in devicetree: '{model}'. (Hint: set the `--rpi-model` flag)"
                 );
             },
         )
     }
 }
 
+impl FromStr for PiModule {
+    type Err = anyhow::Error;
+
+    /// Parses a MODELSPEC, suggesting the closest one for a typo
+    fn from_str(s: &str) -> Result<Self> {
+        let spec = s
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .collect::<String>()
+            .to_lowercase();
+        if let Some((_, pi_module)) = MODELSPECS.iter().find(|(alias, _)| *alias == spec) {
+            return Ok(*pi_module);
+        }
+        if PI_ZERO_SPECS.contains(&spec.as_str()) {
+            bail!(
+                "MODELSPEC '{s}' names the original Pi Zero, which isn't supported. For a Pi Zero \
+                2 W, use 'zero2w'."
+            );
+        }
+
+        let valid = MODELSPECS
+            .iter()
+            .map(|(alias, _)| *alias)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let candidates = MODELSPECS
+            .iter()
+            .map(|(alias, _)| (edit_distance(alias, &spec), *alias))
+            .filter(|(distance, _)| *distance <= 2)
+            .collect::<Vec<_>>();
+        let Some(closest) = candidates.iter().map(|(distance, _)| *distance).min() else {
+            bail!("unknown MODELSPEC '{s}'. Valid: {valid}");
+        };
+
+        // a typo can be as close to several MODELSPECs, e.g. `cm45` to `cm4` and `cm5`
+        let suggestions = candidates
+            .iter()
+            .filter(|(distance, _)| *distance == closest)
+            .map(|(_, alias)| format!("'{alias}'"))
+            .collect::<Vec<_>>();
+        let suggestion = match suggestions.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} or {last}", rest.join(", ")),
+            _ => suggestions.concat(),
+        };
+        bail!("unknown MODELSPEC '{s}'. Did you mean {suggestion}? Valid: {valid}")
+    }
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+impl BoardRevision {
+    /// Reads the revision code from `proc/cpuinfo` under `root`, or from the devicetree where
+    /// cpuinfo doesn't have it. `root` is `/` except for fixture trees.
+    #[must_use]
+    pub fn detect(root: &Path) -> Option<Self> {
+        let from_cpuinfo = fs::read_to_string(root.join("proc/cpuinfo"))
+            .ok()
+            .and_then(|cpuinfo| {
+                cpuinfo.lines().find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    (key.trim() == "Revision")
+                        .then(|| u32::from_str_radix(value.trim(), 16).ok())
+                        .flatten()
+                })
+            });
+        let code = from_cpuinfo.or_else(|| {
+            let bytes =
+                fs::read(root.join("sys/firmware/devicetree/base/system/linux,revision")).ok()?;
+            Some(u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?))
+        })?;
+
+        Self::decode(code)
+    }
+
+    /// Decodes a new-style revision code, laid out as `NOQuuuWuFMMMCCCCPPPPTTTTTTTTRRRR`. Old-style
+    /// codes, used only by the first Raspberry Pi boards, aren't decoded.
+    #[must_use]
+    pub fn decode(code: u32) -> Option<Self> {
+        let new_style = (code >> 23) & 1 == 1;
+        if !new_style {
+            return None;
+        }
+
+        let board_type = (code >> 4) & 0xff;
+        let (model, pi_module) = BOARD_TYPES
+            .iter()
+            .find(|(id, _, _)| *id == board_type)
+            .map_or_else(
+                || (format!("Unknown board type 0x{board_type:02x}"), None),
+                |(_, model, pi_module)| ((*model).to_string(), *pi_module),
+            );
+        let manufacturer = MANUFACTURERS
+            .get(((code >> 16) & 0xf) as usize)
+            .map_or_else(|| "Unknown".to_string(), |name| (*name).to_string());
+
+        Some(Self {
+            code: format!("{code:x}"),
+            model,
+            memory_mb: 256 << ((code >> 20) & 0x7),
+            manufacturer,
+            revision: format!("1.{}", code & 0xf),
+            pi_module,
+        })
+    }
+}
+
+impl Display for BoardRevision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let memory = if self.memory_mb >= 1024 {
+            format!("{}GB", self.memory_mb / 1024)
+        } else {
+            format!("{}MB", self.memory_mb)
+        };
+        write!(
+            f,
+            "{} rev {}, {memory}, {} ({})",
+            self.model, self.revision, self.manufacturer, self.code
+        )
+    }
+}
+
 impl ZymbitModule {
-    // TODO: figure out a better way to get the module (use C API probably)
-    //  and properly detect an HSM6
+    /// I2C address Zymkey 4i, HSM4 and HSM6 modules answer on
+    const I2C_ADDRESS: &'static str = "0030";
+
     fn get() -> Result<Self> {
-        if glob::glob("/dev/zscm*")
-            .context("Failed to check '/dev/zscm*'")?
-            .count()
-            > 0
+        Self::detect(Path::new("/"))
+    }
+
+    /// Whether the module holds a key `zbcli` can sign with, if that is known. Detection can miss
+    /// a fitted module, so only an identified Zymkey 4i or HSM4 rules it out.
+    #[must_use]
+    pub fn supports_hardware_signing(self) -> Option<bool> {
+        match self {
+            ZymbitModule::Scm | ZymbitModule::Hsm6 => Some(true),
+            ZymbitModule::Zymkey4i | ZymbitModule::Hsm4 => Some(false),
+            ZymbitModule::Unidentified | ZymbitModule::Absent => None,
+        }
+    }
+
+    /// Detects the module from the filesystem under `root`, which is `/` except for fixture
+    /// trees:
+    ///
+    /// - `dev/zscm*`: the device nodes of an SCM
+    /// - `sys/bus/i2c/devices/*-0030`: a module on the I2C bus, identified by the device's `name`
+    ///   or its devicetree `compatible` string
+    /// - `var/lib/zymbit`: zkifc state. zkifc usually drives the module from userspace through
+    ///   `/dev/i2c-1`, with no kernel client at 0x30, so a fitted module may show up only here.
+    ///
+    /// The module is only reported absent when none of these is found.
+    pub fn detect(root: &Path) -> Result<Self> {
+        let scm_nodes = root.join("dev/zscm*");
+        if glob::glob(&scm_nodes.to_string_lossy())
+            .context(format!("Failed to check '{}'", scm_nodes.display()))?
+            .next()
+            .is_some()
         {
-            Ok(ZymbitModule::Scm)
+            return Ok(ZymbitModule::Scm);
+        }
+
+        let mut present = false;
+        let i2c_devices = root.join("sys/bus/i2c/devices");
+        if let Ok(entries) = fs::read_dir(&i2c_devices) {
+            for entry in entries.flatten() {
+                let is_module = entry
+                    .file_name()
+                    .to_string_lossy()
+                    .rsplit_once('-')
+                    .is_some_and(|(_, address)| address == Self::I2C_ADDRESS);
+                if !is_module {
+                    continue;
+                }
+
+                present = true;
+                let device = entry.path();
+                let identity = ["name", "of_node/compatible"]
+                    .iter()
+                    .filter_map(|file| fs::read(device.join(file)).ok())
+                    .find_map(|content| Self::from_identity(&String::from_utf8_lossy(&content)));
+                if let Some(module) = identity {
+                    return Ok(module);
+                }
+            }
+        }
+
+        if root.join("var/lib/zymbit").is_dir() {
+            present = true;
+        }
+
+        Ok(if present {
+            ZymbitModule::Unidentified
         } else {
-            Ok(ZymbitModule::Zymkey)
+            ZymbitModule::Absent
+        })
+    }
+
+    /// Recognises a module by a name such as `hsm6` or a devicetree compatible string such as
+    /// `zymbit,zymkey4`. Compatible strings are NUL-separated lists, which this reads as one.
+    fn from_identity(identity: &str) -> Option<Self> {
+        let identity = identity.to_lowercase();
+        if identity.contains("hsm6") {
+            Some(ZymbitModule::Hsm6)
+        } else if identity.contains("hsm4") {
+            Some(ZymbitModule::Hsm4)
+        } else if identity.contains("scm") {
+            Some(ZymbitModule::Scm)
+        } else if identity.contains("zymkey") {
+            Some(ZymbitModule::Zymkey4i)
+        } else {
+            None
         }
     }
 }
@@ -234,6 +815,109 @@ pub fn add_executable_permission(file: &PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Replaces `dest` with the bytes read from `content` such that a power loss leaves either the old
+/// or the new file in place, never a truncated one.
+///
+/// The content is written to a temporary file in the same directory, synced, optionally made
+/// executable and renamed over `dest`, after which the directory itself is synced. Temporary files
+/// left behind by an earlier run that lost power are removed first.
+pub fn replace_file_atomically(
+    dest: &Path,
+    content: &mut impl Read,
+    executable: bool,
+) -> Result<()> {
+    let (Some(dir), Some(file_name)) = (dest.parent(), dest.file_name()) else {
+        bail!("invalid install path '{}'", dest.display());
+    };
+    let tmp_prefix = format!(".{}.tmp-", file_name.to_string_lossy());
+    remove_stale_tmp_files(dir, &tmp_prefix);
+
+    // the PID alone can repeat across boots, so the time makes the name unique
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let tmp_path = dir.join(format!("{tmp_prefix}{}-{nanos}", std::process::id()));
+
+    let result = write_and_rename(&tmp_path, dest, content, executable);
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result?;
+
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .context(format!("Failed to sync directory ({})", dir.display()))
+}
+
+/// Removes the `<tmp_prefix><pid>-<time>` files in `dir` whose process is gone. A PID that is
+/// running again after a reboot is recognised only if it is this process's own.
+fn remove_stale_tmp_files(dir: &Path, tmp_prefix: &str) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let own_pid = std::process::id().to_string();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(suffix) = name
+            .to_string_lossy()
+            .strip_prefix(tmp_prefix)
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let pid = suffix
+            .split_once('-')
+            .map_or(suffix.as_str(), |(pid, _)| pid);
+        if pid == own_pid || !Path::new("/proc").join(pid).exists() {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn write_and_rename(
+    tmp_path: &PathBuf,
+    dest: &Path,
+    content: &mut impl Read,
+    executable: bool,
+) -> Result<()> {
+    let mut tmp_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o644)
+        .open(tmp_path)
+        .context(format!("Failed to create ({})", tmp_path.display()))?;
+    io::copy(content, &mut tmp_file)
+        .context(format!("Failed to write ({})", tmp_path.display()))?;
+    tmp_file
+        .sync_all()
+        .context(format!("Failed to sync ({})", tmp_path.display()))?;
+    drop(tmp_file);
+
+    if executable {
+        add_executable_permission(tmp_path)?;
+    }
+
+    // Renaming over a running executable is fine on Linux, but some filesystems still refuse it
+    // with "text file busy". Unlinking first keeps the running process's inode alive and frees
+    // the name for the new file.
+    match fs::rename(tmp_path, dest) {
+        Err(e) if e.kind() == io::ErrorKind::ExecutableFileBusy => {
+            fs::remove_file(dest).context(format!(
+                "Failed to remove busy executable ({})",
+                dest.display()
+            ))?;
+            fs::rename(tmp_path, dest)
+        }
+        result => result,
+    }
+    .context(format!(
+        "Failed to move {} into place ({})",
+        tmp_path.display(),
+        dest.display()
+    ))
+}
+
 #[allow(dead_code)]
 fn boot_mountpoint() -> Result<PathBuf> {
     let Ok(mounts) = fs::read_to_string("/etc/mtab") else {
@@ -248,12 +932,243 @@ fn boot_mountpoint() -> Result<PathBuf> {
                 .nth(1)
                 .unwrap_or_else(|| panic!("unable to parse system mount table"))
         })
-        .nth(0)
-        .unwrap_or_else(|| panic!("{} not mounted!", ROOTDEV_NAME));
+        .next()
+        .unwrap_or_else(|| panic!("{ROOTDEV_NAME} not mounted!"));
 
     Ok(PathBuf::from(mountpoint))
 }
 
-fn os_release() -> Result<String> {
-    fs::read_to_string("/etc/os-release").context("unable to determine OS type")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fixture tree holding `files`, given as paths relative to its root and their content
+    fn fixture(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    fn detect(files: &[(&str, &str)]) -> ZymbitModule {
+        ZymbitModule::detect(fixture(files).path()).unwrap()
+    }
+
+    #[test]
+    fn detects_scm_by_device_node() {
+        assert_eq!(detect(&[("dev/zscm0", "")]), ZymbitModule::Scm);
+    }
+
+    #[test]
+    fn detects_i2c_modules_by_name() {
+        let hsm6 = [("sys/bus/i2c/devices/1-0030/name", "hsm6\n")];
+        let hsm4 = [("sys/bus/i2c/devices/1-0030/name", "HSM4\n")];
+        assert_eq!(detect(&hsm6), ZymbitModule::Hsm6);
+        assert_eq!(detect(&hsm4), ZymbitModule::Hsm4);
+    }
+
+    #[test]
+    fn detects_zymkey_by_compatible_string() {
+        let files = [
+            ("sys/bus/i2c/devices/1-0030/name", "dummy\n"),
+            (
+                "sys/bus/i2c/devices/1-0030/of_node/compatible",
+                "zymbit,zymkey4\0",
+            ),
+        ];
+        assert_eq!(detect(&files), ZymbitModule::Zymkey4i);
+    }
+
+    #[test]
+    fn unknown_device_at_module_address_is_unidentified() {
+        let files = [
+            ("sys/bus/i2c/devices/1-0030/name", "dummy\n"),
+            ("sys/bus/i2c/devices/1-0050/name", "hsm6\n"),
+        ];
+        assert_eq!(detect(&files), ZymbitModule::Unidentified);
+    }
+
+    #[test]
+    fn zkifc_only_module_is_unidentified() {
+        // an HSM6 driven by zkifc through /dev/i2c-1, with no kernel client at 0x30
+        let files = [
+            ("dev/i2c-1", ""),
+            ("sys/bus/i2c/devices/i2c-1/name", "bcm2835 (i2c@7e804000)\n"),
+            ("sys/bus/i2c/devices/1-0050/name", "24c32\n"),
+            ("var/lib/zymbit/zkifc.conf", ""),
+        ];
+        assert_eq!(detect(&files), ZymbitModule::Unidentified);
+    }
+
+    #[test]
+    fn absent_without_any_module_evidence() {
+        let files = [
+            ("dev/i2c-1", ""),
+            ("sys/bus/i2c/devices/1-0050/name", "24c32\n"),
+        ];
+        assert_eq!(detect(&files), ZymbitModule::Absent);
+        assert_eq!(detect(&[]), ZymbitModule::Absent);
+    }
+
+    #[test]
+    fn parses_quoted_and_escaped_os_release_values() {
+        let release = OsRelease::parse(
+            "# comment\n\
+             ID='ubuntu'\n\
+             \n\
+             VERSION_ID=\"24.04\"\n\
+             VERSION_CODENAME=noble\n\
+             PRETTY_NAME=\"Ubuntu \\\"Noble\\\" \\$RELEASE \\\\ 24.04\"\n",
+        );
+
+        assert_eq!(release.id, "ubuntu");
+        assert_eq!(release.version_id.as_deref(), Some("24.04"));
+        assert_eq!(release.version_codename.as_deref(), Some("noble"));
+        assert_eq!(
+            release.pretty_name.as_deref(),
+            Some("Ubuntu \"Noble\" $RELEASE \\ 24.04")
+        );
+        assert_eq!(OsRelease::parse("VERSION_ID=12\n").id, "linux");
+    }
+
+    #[test]
+    fn identifies_raspbian_by_id_like() {
+        let release = OsRelease::parse(
+            "PRETTY_NAME=\"Raspbian GNU/Linux 11 (bullseye)\"\n\
+             VERSION_ID=\"11\"\n\
+             VERSION_CODENAME=bullseye\n\
+             ID=raspbian\n\
+             ID_LIKE=debian\n",
+        );
+        assert_eq!(release.id_like, ["debian"]);
+
+        let os = OperatingSystem::identify(release).unwrap();
+        assert_eq!(os.support.key, "rpi_bullseye");
+        assert!(os.check_supports(PiModule::Rpi4_64).is_ok());
+        assert!(os.check_supports(PiModule::Rpi5_64).is_err());
+    }
+
+    #[test]
+    fn refuses_unsupported_ubuntu_release() {
+        let release = OsRelease::parse(
+            "ID=ubuntu\nID_LIKE=debian\nVERSION_ID=\"20.04\"\nVERSION_CODENAME=focal\n",
+        );
+
+        let error = OperatingSystem::identify(release).unwrap_err().to_string();
+        assert!(error.starts_with("Unsupported OS"), "{error}");
+        assert!(error.contains("Ubuntu 24.04"), "{error}");
+    }
+
+    #[test]
+    fn decodes_new_style_revision_codes() {
+        let pi4 = BoardRevision::decode(0x00c0_3114).unwrap();
+        assert_eq!(pi4.model, "Raspberry Pi 4 Model B");
+        assert_eq!(pi4.memory_mb, 4096);
+        assert_eq!(pi4.manufacturer, "Sony UK");
+        assert_eq!(pi4.revision, "1.4");
+        assert_eq!(pi4.pi_module, Some(PiModule::Rpi4_64));
+        assert_eq!(
+            pi4.to_string(),
+            "Raspberry Pi 4 Model B rev 1.4, 4GB, Sony UK (c03114)"
+        );
+
+        let pi5 = BoardRevision::decode(0x00d0_4170).unwrap();
+        assert_eq!(pi5.model, "Raspberry Pi 5");
+        assert_eq!(pi5.memory_mb, 8192);
+        assert_eq!(pi5.revision, "1.0");
+        assert_eq!(pi5.pi_module, Some(PiModule::Rpi5_64));
+
+        let pi3 = BoardRevision::decode(0x00a0_2082).unwrap();
+        assert_eq!(pi3.model, "Raspberry Pi 3 Model B");
+        assert_eq!(pi3.memory_mb, 1024);
+        assert_eq!(pi3.revision, "1.2");
+        assert_eq!(pi3.pi_module, None);
+    }
+
+    #[test]
+    fn leaves_old_style_revision_codes_undecoded() {
+        assert!(BoardRevision::decode(0x000e).is_none());
+    }
+
+    #[test]
+    fn reads_revision_from_cpuinfo_or_devicetree() {
+        let cpuinfo = fixture(&[(
+            "proc/cpuinfo",
+            "processor\t: 0\nHardware\t: BCM2835\nRevision\t: c03114\n",
+        )]);
+        let board = BoardRevision::detect(cpuinfo.path()).unwrap();
+        assert_eq!(board.code, "c03114");
+
+        let devicetree = tempfile::tempdir().unwrap();
+        let revision = devicetree
+            .path()
+            .join("sys/firmware/devicetree/base/system/linux,revision");
+        fs::create_dir_all(revision.parent().unwrap()).unwrap();
+        fs::write(&revision, 0x00d0_4170_u32.to_be_bytes()).unwrap();
+        let board = BoardRevision::detect(devicetree.path()).unwrap();
+        assert_eq!(board.code, "d04170");
+
+        assert!(BoardRevision::detect(fixture(&[]).path()).is_none());
+    }
+
+    #[test]
+    fn parses_modelspecs() {
+        assert_eq!("cm4".parse::<PiModule>().unwrap(), PiModule::Rpi4_64);
+        assert_eq!("Pi 4".parse::<PiModule>().unwrap(), PiModule::Rpi4_64);
+        assert_eq!("CM5-Lite".parse::<PiModule>().unwrap(), PiModule::Rpi5_64);
+        assert_eq!("pizero2w".parse::<PiModule>().unwrap(), PiModule::Rpi0_64);
+        assert_eq!("Pi_Zero_2W".parse::<PiModule>().unwrap(), PiModule::Rpi0_64);
+    }
+
+    #[test]
+    fn suggests_closest_modelspec() {
+        let error = "pi55".parse::<PiModule>().unwrap_err().to_string();
+        assert!(error.contains("Did you mean 'pi5'?"), "{error}");
+
+        let error = "cm45".parse::<PiModule>().unwrap_err().to_string();
+        assert!(
+            error.contains("Did you mean 'cm4', 'cm4s' or 'cm5'?"),
+            "{error}"
+        );
+
+        let error = "Pi54".parse::<PiModule>().unwrap_err().to_string();
+        assert!(error.contains("Did you mean 'pi4' or 'pi5'?"), "{error}");
+
+        let error = "banana".parse::<PiModule>().unwrap_err().to_string();
+        assert!(!error.contains("Did you mean"), "{error}");
+    }
+
+    #[test]
+    fn refuses_original_pi_zero() {
+        let error = "pizero".parse::<PiModule>().unwrap_err().to_string();
+        assert!(error.contains("use 'zero2w'"), "{error}");
+        assert!("Pi Zero W".parse::<PiModule>().is_err());
+    }
+
+    #[test]
+    fn replaces_file_despite_leftover_tmp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("zbcli");
+        fs::write(&dest, "old").unwrap();
+        // left by this PID in an earlier boot, by a process that is gone, and by a running one
+        let own = dir
+            .path()
+            .join(format!(".zbcli.tmp-{}", std::process::id()));
+        let gone = dir.path().join(".zbcli.tmp-4194305-1");
+        let running = dir.path().join(".zbcli.tmp-1-1");
+        for leftover in [&own, &gone, &running] {
+            fs::write(leftover, "partial").unwrap();
+        }
+
+        replace_file_atomically(&dest, &mut "new".as_bytes(), true).unwrap();
+
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert!(!own.exists());
+        assert!(!gone.exists());
+        assert!(running.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
 }
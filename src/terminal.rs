@@ -25,6 +25,76 @@
 // of the parties have executed a special contract specifically governing such use.
 // -------------------------------------------------------------------------------------------------------
 
+use std::{
+    io::{self, IsTerminal, Write},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+/// How results are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+
+    /// A single JSON document on stdout, for scripts. Messages meant for people go to stderr.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => bail!("unknown output format '{s}', expected 'text' or 'json'"),
+        }
+    }
+}
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_output_format(format: OutputFormat) {
+    JSON_OUTPUT.store(format == OutputFormat::Json, Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Whether the user can be asked to choose. Prompts are drawn on stderr and read from stdin, or
+/// from the controlling terminal when stdin is a pipe as in `curl ... | sudo bash`.
+#[must_use]
+pub fn can_prompt() -> bool {
+    io::stderr().is_terminal()
+        && (io::stdin().is_terminal() || std::fs::File::open("/dev/tty").is_ok())
+}
+
+/// Prints `value` as the JSON result of the run
+pub fn print_json(value: &impl Serialize) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).context("Failed to serialize output")?;
+    println!("{json}");
+    Ok(())
+}
+
+/// `println!` for messages meant for people. They go to stderr when stdout carries JSON.
+macro_rules! message {
+    ($($arg:tt)*) => {
+        if $crate::terminal::json_output() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use message;
+
 #[allow(dead_code)]
 pub enum OutputColor {
     Green,
@@ -48,3 +118,143 @@ pub fn formatted_left_output(output: &str, color: &OutputColor) -> String {
         output
     )
 }
+
+/// Byte-count progress bar with transfer rate and ETA, drawn on stdout, or on stderr when stdout
+/// carries JSON. It draws nothing when that isn't a terminal.
+pub struct ProgressBar {
+    label: String,
+    total: Option<u64>,
+    position: u64,
+    initial: u64,
+    start: Instant,
+    last_draw: Option<Instant>,
+    enabled: bool,
+}
+
+impl ProgressBar {
+    const WIDTH: usize = 30;
+    const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// `total` is the expected number of bytes, if known
+    #[must_use]
+    pub fn new(label: &str, total: Option<u64>) -> Self {
+        Self {
+            label: label.to_string(),
+            total,
+            position: 0,
+            initial: 0,
+            start: Instant::now(),
+            last_draw: None,
+            enabled: if json_output() {
+                io::stderr().is_terminal()
+            } else {
+                io::stdout().is_terminal()
+            },
+        }
+    }
+
+    /// Starts from `bytes` already transferred by an earlier attempt. They don't count towards
+    /// the transfer rate.
+    pub fn resume_from(&mut self, bytes: u64) {
+        self.position = bytes;
+        self.initial = bytes;
+    }
+
+    pub fn inc(&mut self, bytes: u64) {
+        self.position += bytes;
+
+        if self
+            .last_draw
+            .is_none_or(|last| last.elapsed() >= Self::REDRAW_INTERVAL)
+        {
+            self.draw();
+        }
+    }
+
+    /// Draws the final state and moves to the next line
+    pub fn finish(&mut self) {
+        if self.enabled {
+            self.draw();
+            message!();
+        }
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn draw(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            (self.position - self.initial) as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        let status = match self.total {
+            Some(total) if total > 0 => {
+                let filled =
+                    (self.position.min(total) as f64 / total as f64 * Self::WIDTH as f64) as usize;
+                let eta = if rate > 0.0 {
+                    format_duration((total.saturating_sub(self.position) as f64 / rate) as u64)
+                } else {
+                    "--:--".to_string()
+                };
+
+                format!(
+                    "[{}{}] {} / {}  {}/s  ETA {eta}",
+                    "#".repeat(filled),
+                    " ".repeat(Self::WIDTH - filled),
+                    format_bytes(self.position),
+                    format_bytes(total),
+                    format_bytes(rate as u64),
+                )
+            }
+            _ => format!(
+                "{}  {}/s",
+                format_bytes(self.position),
+                format_bytes(rate as u64)
+            ),
+        };
+
+        let line = format!(
+            "\r{} {status}\x1b[K",
+            formatted_left_output(&self.label, &OutputColor::Blue)
+        );
+        let _ = if json_output() {
+            io::stderr().write_all(line.as_bytes())
+        } else {
+            io::stdout()
+                .write_all(line.as_bytes())
+                .and(io::stdout().flush())
+        };
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
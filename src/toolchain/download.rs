@@ -0,0 +1,377 @@
+use std::{
+    fs::{self, OpenOptions},
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use reqwest::{header, Client, Proxy, StatusCode};
+
+use crate::terminal::{formatted_left_output, OutputColor, ProgressBar};
+
+use super::store::STATE_DIR;
+
+/// Where assets are downloaded to before they are verified and installed. Interrupted downloads
+/// are kept here and resumed by the next run.
+#[must_use]
+pub fn staging_dir() -> PathBuf {
+    Path::new(STATE_DIR).join("downloads")
+}
+
+/// How network requests are made
+#[derive(Debug, Clone)]
+pub struct NetworkOptions {
+    /// Times a download is retried after a transient failure
+    pub retries: u32,
+
+    /// Limit on connecting and on waiting for more data from the server
+    pub timeout: Duration,
+
+    /// Proxy URL for all requests. Without one, the usual `HTTPS_PROXY` and `HTTP_PROXY`
+    /// environment variables apply.
+    pub proxy: Option<String>,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            timeout: Duration::from_secs(30),
+            proxy: None,
+        }
+    }
+}
+
+pub fn http_client(options: &NetworkOptions) -> Result<Client> {
+    let mut builder = Client::builder()
+        .user_agent(concat!("zb-installer/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(options.timeout)
+        .read_timeout(options.timeout);
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(Proxy::all(proxy).context(format!("Invalid proxy URL '{proxy}'"))?);
+    }
+
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Fetches release assets over HTTP, retrying transient failures with exponential backoff
+pub struct Downloader {
+    client: Client,
+    retries: u32,
+}
+
+impl Downloader {
+    const BASE_DELAY: Duration = Duration::from_secs(1);
+    const MAX_DELAY: Duration = Duration::from_secs(30);
+
+    pub fn new(options: &NetworkOptions) -> Result<Self> {
+        Ok(Self {
+            client: http_client(options)?,
+            retries: options.retries,
+        })
+    }
+
+    /// Reads a small file, such as a checksum or signature, into memory
+    pub async fn get_bytes(&self, url: &str) -> Result<Vec<u8>> {
+        self.with_retries(url, || async {
+            let content = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .context(format!("Failed to download ({url})"))?
+                .bytes()
+                .await
+                .context(format!("Failed to download ({url})"))?;
+
+            Ok(content.to_vec())
+        })
+        .await
+    }
+
+    /// Streams `url` to `dest` chunk by chunk, showing a progress bar labelled `label`.
+    ///
+    /// The content is written to `dest` with a `.part` suffix and only renamed once complete. A
+    /// `.part` file left by an earlier attempt is resumed with an HTTP range request, but only if
+    /// the server still has the same file: the `ETag` or `Last-Modified` of the first response is
+    /// kept beside it and sent as `If-Range`, since release assets can be replaced in place.
+    pub async fn to_file(&self, url: &str, dest: &Path, label: &str) -> Result<()> {
+        let part_path = suffixed(dest, ".part");
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir).context(format!("Failed to create ({})", dir.display()))?;
+        }
+
+        self.with_retries(url, || self.resume(url, &part_path, label))
+            .await?;
+
+        fs::rename(&part_path, dest).context(format!(
+            "Failed to move {} into place ({})",
+            part_path.display(),
+            dest.display()
+        ))?;
+        let _ = fs::remove_file(suffixed(&part_path, ".validator"));
+
+        Ok(())
+    }
+
+    /// Downloads the rest of `url` into `part_path`, or all of it if the server can't resume
+    async fn resume(&self, url: &str, part_path: &Path, label: &str) -> Result<()> {
+        let validator_path = suffixed(part_path, ".validator");
+        let offset = fs::metadata(part_path).map_or(0, |metadata| metadata.len());
+        let validator = fs::read_to_string(&validator_path).ok();
+
+        // without a validator the partial file can't be matched to the server's, so it is
+        // downloaded again in full
+        let mut req = self.client.get(url);
+        if let (true, Some(validator)) = (offset > 0, &validator) {
+            req = req
+                .header(header::RANGE, format!("bytes={offset}-"))
+                .header(header::IF_RANGE, validator.trim());
+        }
+        let res = req
+            .send()
+            .await
+            .context(format!("Failed to download ({url})"))?;
+
+        if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // the partial file doesn't belong to what the server has now; start over
+            fs::remove_file(part_path)
+                .context(format!("Failed to remove ({})", part_path.display()))?;
+            return Box::pin(self.resume(url, part_path, label)).await;
+        }
+        let mut res = res
+            .error_for_status()
+            .context(format!("Failed to download ({url})"))?;
+
+        let resumed = res.status() == StatusCode::PARTIAL_CONTENT;
+        if !resumed {
+            match validator_of(&res) {
+                Some(validator) => fs::write(&validator_path, validator),
+                None => fs::remove_file(&validator_path).or_else(|e| {
+                    if e.kind() == std::io::ErrorKind::NotFound {
+                        Ok(())
+                    } else {
+                        Err(e)
+                    }
+                }),
+            }
+            .context(format!("Failed to write ({})", validator_path.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(part_path)
+            .context(format!("Failed to open ({})", part_path.display()))?;
+
+        let start = if resumed { offset } else { 0 };
+        let mut progress = ProgressBar::new(label, res.content_length().map(|len| len + start));
+        progress.resume_from(start);
+
+        let result: Result<()> = async {
+            while let Some(chunk) = res
+                .chunk()
+                .await
+                .context(format!("Failed to download ({url})"))?
+            {
+                file.write_all(&chunk)
+                    .context(format!("Failed to write ({})", part_path.display()))?;
+                progress.inc(chunk.len() as u64);
+            }
+            Ok(())
+        }
+        .await;
+        progress.finish();
+        result?;
+
+        file.sync_all()
+            .context(format!("Failed to sync ({})", part_path.display()))
+    }
+
+    async fn with_retries<T, F, Fut>(&self, url: &str, mut attempt: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut failures = 0;
+        loop {
+            match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(e) if failures < self.retries && is_transient(&e) => {
+                    let delay = backoff(failures);
+                    failures += 1;
+                    eprintln!(
+                        "{} {e:#}; retrying in {:.1} s ({failures}/{})",
+                        formatted_left_output("Warning", &OutputColor::Yellow),
+                        delay.as_secs_f64(),
+                        self.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    return Err(e.context(format!(
+                        "Download of {url} failed after {} attempt(s)",
+                        failures + 1
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Connection problems, timeouts, rate limiting and server errors are worth retrying; anything
+/// else, like a missing asset or a full disk, is not
+fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain()
+        .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .is_some_and(|e| match e.status() {
+            Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+            None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+        })
+}
+
+/// Exponential backoff with jitter: a random delay between half and all of
+/// `BASE_DELAY * 2^failures`, capped at `MAX_DELAY`
+fn backoff(failures: u32) -> Duration {
+    let ceiling = Downloader::BASE_DELAY
+        .saturating_mul(2_u32.saturating_pow(failures))
+        .min(Downloader::MAX_DELAY);
+
+    // the sub-second part of the clock is random enough to spread out retries
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let fraction = 0.5 + f64::from(jitter % 1000) / 2000.0;
+
+    ceiling.mul_f64(fraction)
+}
+
+/// What identifies this version of the response's content for `If-Range`: a strong `ETag`, or
+/// else `Last-Modified`
+fn validator_of(res: &reqwest::Response) -> Option<String> {
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    header(header::ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(header::LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut suffixed = path.as_os_str().to_owned();
+    suffixed.push(suffix);
+    PathBuf::from(suffixed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const ASSET: &str = "zbcli built from the new commit";
+    const ETAG: &str = "\"new\"";
+
+    /// Serves [`ASSET`] with [`ETAG`], honouring a range only when `If-Range` matches, and
+    /// records the request headers it got
+    async fn serve() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                seen.lock().unwrap().push(request.clone());
+
+                let header = |name: &str| {
+                    request
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(str::trim)
+                };
+                let range_start = header("range: bytes=")
+                    .filter(|_| header("if-range:") == Some(ETAG))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+                let (status, body) = match range_start {
+                    Some(start) => ("206 Partial Content", &ASSET[start..]),
+                    None => ("200 OK", ASSET),
+                };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nETag: {ETAG}\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (format!("http://{address}/zbcli-rpi5"), requests)
+    }
+
+    /// Downloads to a `dest` with a `.part` file holding `partial` and, if given, `validator`
+    async fn download(partial: &str, validator: Option<&str>) -> (String, Vec<String>) {
+        let (url, requests) = serve().await;
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("zbcli-rpi5");
+        fs::write(suffixed(&dest, ".part"), partial).unwrap();
+        if let Some(validator) = validator {
+            fs::write(suffixed(&dest, ".part.validator"), validator).unwrap();
+        }
+
+        let downloader = Downloader::new(&NetworkOptions::default()).unwrap();
+        downloader
+            .to_file(&url, &dest, "Downloading")
+            .await
+            .unwrap();
+
+        assert!(!suffixed(&dest, ".part.validator").exists());
+        let requests = requests.lock().unwrap().clone();
+        (fs::read_to_string(&dest).unwrap(), requests)
+    }
+
+    #[tokio::test]
+    async fn resumes_unchanged_asset() {
+        let (content, requests) = download(&ASSET[..10], Some(ETAG)).await;
+
+        assert_eq!(content, ASSET);
+        assert!(requests[0].contains("range: bytes=10-"));
+    }
+
+    #[tokio::test]
+    async fn starts_over_when_asset_was_replaced() {
+        let (content, requests) = download("zbcli built from the old", Some("\"old\"")).await;
+
+        assert_eq!(content, ASSET);
+        assert!(requests[0].contains("if-range: \"old\""));
+    }
+
+    #[tokio::test]
+    async fn starts_over_without_validator() {
+        let (content, requests) = download("zbcli built from the old", None).await;
+
+        assert_eq!(content, ASSET);
+        assert!(!requests[0].contains("range:"));
+    }
+}
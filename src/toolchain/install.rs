@@ -1,81 +1,544 @@
-use std::{fs::File, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::OnceLock,
+};
 
 use anyhow::{bail, Context, Result};
 use dialoguer::theme::ColorfulTheme;
-use reqwest;
+use semver::Version;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     system,
-    terminal::{formatted_left_output, OutputColor},
+    terminal::{formatted_left_output, message, OutputColor},
+};
+
+use super::{
+    download::{self, Downloader, NetworkOptions},
+    source::{Release, ReleaseSource},
+    state,
+    store::VersionStore,
+    verify,
+    version::{self, VersionSpec},
 };
 
-use super::version;
+/// Asset file(s) to install from instead of downloading a release
+#[derive(Debug)]
+pub enum LocalSource {
+    /// A single asset file. Its checksum and signature are looked for next to it.
+    File(PathBuf),
+
+    /// A directory holding assets named as in a release, with their checksums and signatures
+    Dir(PathBuf),
+}
+
+/// What an install put in place
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Installed {
+    pub tool: String,
+
+    /// Release tag, or the name it was stored under for local installs
+    pub version: String,
+    pub asset: String,
+    pub channel: String,
+    pub path: PathBuf,
+
+    /// Lowercase hex SHA-256 digest of the installed binary
+    pub sha256: String,
+}
+
+/// Where an asset and the checksum and signature files published alongside it are read from
+pub(super) enum AssetSource<'a> {
+    Release(&'a Release, &'a Downloader),
+    Dir(&'a Path),
+}
+
+impl AssetSource<'_> {
+    /// Returns the path of the file `name`, downloading it to the staging directory first if
+    /// needed, or `None` if the source doesn't have it
+    pub(super) async fn fetch(&self, name: &str) -> Result<Option<PathBuf>> {
+        match self {
+            AssetSource::Release(release, downloader) => {
+                let Some(asset) = release.asset(name) else {
+                    return Ok(None);
+                };
+
+                let dest = download::staging_dir().join(&release.tag).join(name);
+                downloader
+                    .to_file(&asset.url, &dest, "Downloading")
+                    .await
+                    .context(format!("Failed to download '{name}'"))?;
+                Ok(Some(dest))
+            }
+            AssetSource::Dir(dir) => {
+                let path = dir.join(name);
+                Ok(path.is_file().then_some(path))
+            }
+        }
+    }
+
+    /// Returns the contents of the small file `name`, or `None` if the source doesn't have it
+    async fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            AssetSource::Release(release, downloader) => {
+                let Some(asset) = release.asset(name) else {
+                    return Ok(None);
+                };
 
-/// Prompts user for version and installs tool to `/usr/bin/{tag_prefix}`
+                let content = downloader
+                    .get_bytes(&asset.url)
+                    .await
+                    .context(format!("Failed to download '{name}'"))?;
+                Ok(Some(content))
+            }
+            AssetSource::Dir(dir) => {
+                let path = dir.join(name);
+                match fs::read(&path) {
+                    Ok(content) => Ok(Some(content)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e).context(format!("Failed to read ({})", path.display())),
+                }
+            }
+        }
+    }
+
+    pub(super) async fn read_text(&self, name: &str) -> Result<Option<String>> {
+        self.read(name)
+            .await?
+            .map(|content| String::from_utf8(content).context(format!("'{name}' is not text")))
+            .transpose()
+    }
+
+    /// The digest the source lists for `name` itself, rather than in a checksum file
+    fn listed_sha256(&self, name: &str) -> Option<&str> {
+        match self {
+            AssetSource::Release(release, _) => release.asset(name)?.sha256.as_deref(),
+            AssetSource::Dir(_) => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            AssetSource::Release(release, _) => format!("release '{}'", release.tag),
+            AssetSource::Dir(dir) => format!("directory '{}'", dir.display()),
+        }
+    }
+}
+
+/// Prompts user for version and installs tool to [`install_path`]
 ///
 /// `tag_prefix`: `zbcli` in `zbcli-1.1.0`
+///
+/// With a version constraint, the newest matching release that has `target_asset` is installed.
+/// Pre-releases are only offered with `include_prereleases`.
+///
+/// The download is checked against the checksum and signature assets published in the same
+/// release before anything is written. Pass `allow_unverified` to install when the release
+/// publishes none.
 pub async fn prompt(
+    source: &dyn ReleaseSource,
+    network: &NetworkOptions,
     tag_prefix: &str,
     target_asset: &str,
-    zb_version: &Option<String>,
-) -> Result<()> {
-    let releases = version::list(tag_prefix, zb_version, 10_u8).await?;
+    zb_version: Option<&VersionSpec>,
+    include_prereleases: bool,
+    allow_unverified: bool,
+) -> Result<Installed> {
+    let releases =
+        version::list(source, tag_prefix, zb_version, 10_u8, include_prereleases).await?;
 
     let releases_list = releases
         .iter()
-        .filter(|release| {
-            release
-                .assets
-                .iter()
-                .any(|asset| target_asset == asset.name)
-        })
+        .filter(|release| release.asset(target_asset).is_some())
         .collect::<Vec<_>>();
-    if releases_list.is_empty() && zb_version.is_some() {
-        bail!(
-            "asset '{target_asset}' does not exist within the '{}' release",
-            zb_version.as_ref().unwrap()
-        )
+    if let (true, Some(version)) = (releases_list.is_empty(), zb_version) {
+        bail!("asset '{target_asset}' does not exist within the '{version}' release")
     }
 
     let releases_strings = releases_list
         .iter()
-        .map(|release| &release.tag_name)
+        .map(|release| {
+            if version::is_prerelease(release, tag_prefix) {
+                format!("{} (pre-release)", release.tag)
+            } else {
+                release.tag.clone()
+            }
+        })
         .collect::<Vec<_>>();
 
-    let target_release = match zb_version {
-        Some(_) => releases_list[0],
-        None => {
-            let selection = dialoguer::FuzzySelect::with_theme(&ColorfulTheme::default())
-                .with_prompt("Select version")
-                .items(&releases_strings)
-                .interact()
-                .context("Failed to get version selection")?;
-
-            releases_list[selection]
+    let target_release = if let Some(version) = zb_version {
+        if let VersionSpec::Constraint(_) = version {
+            message!("Resolved '{version}' to {}", releases_list[0].tag);
         }
+        releases_list[0]
+    } else {
+        let selection = dialoguer::FuzzySelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select version")
+            .items(&releases_strings)
+            .interact()
+            .context("Failed to get version selection")?;
+
+        releases_list[selection]
     };
 
-    let Some(asset) = target_release
-        .assets
-        .iter()
-        .find(|asset| *target_asset == asset.name)
-    else {
+    let downloader = Downloader::new(network)?;
+    let assets = AssetSource::Release(target_release, &downloader);
+    let Some(asset_path) = assets.fetch(target_asset).await? else {
         bail!(
             "{} failed to find '{target_asset}' from latest release",
             formatted_left_output("Error", &OutputColor::Red)
         )
     };
 
-    let asset_res = reqwest::get(asset.browser_download_url.clone()).await?;
-    let mut content = std::io::Cursor::new(asset_res.bytes().await?);
+    verify_download(&assets, target_asset, &asset_path, allow_unverified).await?;
 
-    println!("Installing {tag_prefix}");
+    let installed = install(
+        tag_prefix,
+        &target_release.tag,
+        target_asset,
+        &source.channel(),
+        &asset_path,
+    )?;
+
+    // the version store keeps its own copy
+    let _ = fs::remove_file(&asset_path);
+    if let Some(staged_dir) = asset_path.parent() {
+        let _ = fs::remove_dir(staged_dir);
+    }
 
-    let zb_path = Path::new("/usr").join("bin").join(tag_prefix);
+    Ok(installed)
+}
 
-    let mut zb_file = File::create(&zb_path)?;
-    std::io::copy(&mut content, &mut zb_file)?;
-    system::add_executable_permission(&zb_path)?;
+/// Installs tool to [`install_path`] from local files, without network access
+///
+/// An exact tag in `zb_version` names the version in the local version store. Otherwise, the
+/// version is named after the asset's digest.
+///
+/// The asset is checked against checksum and signature files stored beside it, as for
+/// [`prompt`]. A file not named `target_asset`, which is likely for another board or signing
+/// variant, is only installed with `force`.
+pub async fn from_local(
+    tag_prefix: &str,
+    target_asset: &str,
+    local: &LocalSource,
+    zb_version: Option<&VersionSpec>,
+    allow_unverified: bool,
+    force: bool,
+) -> Result<Installed> {
+    let (dir, asset_name) = match local {
+        LocalSource::File(path) => {
+            let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+                bail!("invalid asset path '{}'", path.display());
+            };
+            let name = name.to_string_lossy().into_owned();
+            if name != target_asset {
+                if !force {
+                    bail!(
+                        "'{name}' is not '{target_asset}', the asset for this system, so it is \
+                        likely built for another board or signing variant. Pass '--force' to \
+                        install it anyway."
+                    );
+                }
+                eprintln!(
+                    "{} '{name}' is not '{target_asset}', the asset for this system; installing \
+                    it anyway (--force)",
+                    formatted_left_output("Warning", &OutputColor::Yellow)
+                );
+            }
+            (dir, name)
+        }
+        LocalSource::Dir(dir) => (dir.as_path(), target_asset.to_string()),
+    };
+
+    let assets = AssetSource::Dir(dir);
+    let Some(asset_path) = assets.fetch(&asset_name).await? else {
+        bail!(
+            "'{asset_name}' not found in '{}'. Available assets: {}",
+            dir.display(),
+            local_assets(dir, tag_prefix)
+        );
+    };
+
+    verify_download(&assets, &asset_name, &asset_path, allow_unverified).await?;
+
+    let tag = match zb_version {
+        Some(VersionSpec::Tag(tag)) => tag.clone(),
+        _ => format!("local-{}", &verify::sha256_file(&asset_path)?[..12]),
+    };
+
+    install(tag_prefix, &tag, &asset_name, "local", &asset_path)
+}
+
+/// Directory tools are installed to unless configured otherwise
+pub const DEFAULT_INSTALL_DIR: &str = "/usr/bin";
+
+static INSTALL_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Installs tools to `dir` instead of [`DEFAULT_INSTALL_DIR`] for the rest of the run
+pub fn set_install_dir(dir: PathBuf) {
+    let _ = INSTALL_DIR.set(dir);
+}
+
+/// Where `tool` is installed to
+#[must_use]
+pub fn install_path(tool: &str) -> PathBuf {
+    INSTALL_DIR
+        .get()
+        .map_or_else(|| Path::new(DEFAULT_INSTALL_DIR), PathBuf::as_path)
+        .join(tool)
+}
+
+/// Removes the installed `tool`. The versions stored for rollback are deleted as well with `purge`.
+pub fn uninstall(tool: &str, purge: bool) -> Result<()> {
+    let zb_path = install_path(tool);
+    let store = VersionStore::open();
+
+    match fs::remove_file(&zb_path) {
+        Ok(()) => message!("Removed {}", zb_path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if !purge {
+                bail!("{tool} is not installed ({})", zb_path.display());
+            }
+        }
+        Err(e) => return Err(e).context(format!("Failed to remove ({})", zb_path.display())),
+    }
+
+    state::record_uninstall(tool)?;
+    if purge {
+        store.purge(tool)?;
+        message!("Removed the versions of {tool} stored for rollback");
+    } else {
+        store.forget_current(tool)?;
+    }
+
+    Ok(())
+}
+
+/// The installed `tool`'s version, from the install state if that still describes the installed
+/// binary, else from `{tool} --version`
+#[must_use]
+pub fn installed_version(tool: &str, manifest: &state::StateManifest) -> Option<Version> {
+    let zb_path = install_path(tool);
+    if !zb_path.is_file() {
+        return None;
+    }
+
+    let recorded = manifest.get(tool).filter(|record| {
+        verify::sha256_file(&zb_path).is_ok_and(|sha256| sha256 == record.installed.sha256)
+    });
+    if let Some(version) =
+        recorded.and_then(|record| version::tag_version(&record.installed.version, tool))
+    {
+        return Some(version);
+    }
+
+    let output = process::Command::new(&zb_path)
+        .arg("--version")
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .find_map(|word| Version::parse(word.strip_prefix('v').unwrap_or(word)).ok())
+}
+
+/// Checks that the installed `tool` is the binary recorded in the install state, or else in the
+/// version store, when it was installed and verified. Returns its tag.
+pub fn verify_installed(tool: &str) -> Result<String> {
+    let zb_path = install_path(tool);
+    if !zb_path.is_file() {
+        bail!("{tool} is not installed ({})", zb_path.display());
+    }
+
+    let store = VersionStore::open();
+    let (tag, stored) = if let Some(record) = state::StateManifest::load()?.get(tool) {
+        (
+            record.installed.version.clone(),
+            record.installed.sha256.clone(),
+        )
+    } else if let Some(tag) = store.current(tool)? {
+        let stored = verify::sha256_file(&store.binary_path(tool, &tag))?;
+        (tag, stored)
+    } else {
+        bail!(
+            "the installed {tool} was not installed by zb-install, so there is nothing to check it \
+            against. Reinstall it to have it tracked."
+        );
+    };
+
+    let installed = verify::sha256_file(&zb_path)?;
+    if installed != stored {
+        bail!(
+            "the installed {tool} ({installed}) does not match {tag} as installed ({stored}). \
+            It has been modified or replaced."
+        );
+    }
+
+    Ok(tag)
+}
+
+/// Replaces [`install_path`] with the file at `asset_path`, the release asset `asset`, and
+/// keeps a copy of it as `tag`, installed from `channel`
+fn install(
+    tag_prefix: &str,
+    tag: &str,
+    asset: &str,
+    channel: &str,
+    asset_path: &Path,
+) -> Result<Installed> {
+    message!("Installing {tag_prefix} {tag} ({channel})");
+
+    let zb_path = install_path(tag_prefix);
+    if let Some(dir) = zb_path.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create ({})", dir.display()))?;
+    }
+    let store = VersionStore::open();
+    if let Err(e) = store.preserve_untracked(tag_prefix, &zb_path) {
+        eprintln!(
+            "{} failed to keep the currently installed {tag_prefix}: {e:#}",
+            formatted_left_output("Warning", &OutputColor::Yellow)
+        );
+    }
+
+    let mut asset_file =
+        fs::File::open(asset_path).context(format!("Failed to open ({})", asset_path.display()))?;
+    system::replace_file_atomically(&zb_path, &mut asset_file, true)?;
+
+    if let Err(e) = store.save(tag_prefix, tag, channel, asset_path) {
+        eprintln!(
+            "{} failed to keep a copy of {tag} for rollback: {e:#}",
+            formatted_left_output("Warning", &OutputColor::Yellow)
+        );
+    }
+
+    Ok(Installed {
+        tool: tag_prefix.to_string(),
+        version: tag.to_string(),
+        asset: asset.to_string(),
+        channel: channel.to_string(),
+        sha256: verify::sha256_file(&zb_path)?,
+        path: zb_path,
+    })
+}
+
+/// Comma-separated names of the `{tag_prefix}` assets in `dir`, for error messages
+fn local_assets(dir: &Path, tag_prefix: &str) -> String {
+    let mut names = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| name.starts_with(tag_prefix) && !name.contains('.'))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    names.sort();
+
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// The outcome of a check that didn't fail
+enum Verification {
+    /// Describes what the file was verified against
+    Passed(String),
+
+    /// Describes what was looked for and not found
+    Missing(String),
+}
+
+/// Checks the file at `asset_path` against the checksum and signature files published in `assets`.
+/// A missing checksum or signature is reported as a warning instead when `allow_unverified` is
+/// set, but a file that fails either check is never installed.
+pub(super) async fn verify_download(
+    assets: &AssetSource<'_>,
+    target_asset: &str,
+    asset_path: &Path,
+    allow_unverified: bool,
+) -> Result<()> {
+    for check in [
+        verify_checksum(assets, target_asset, asset_path).await,
+        verify_signature(assets, target_asset, asset_path).await,
+    ] {
+        match check.context("Refusing to install a binary that failed verification")? {
+            Verification::Passed(reference) => message!(
+                "{} '{target_asset}' against {reference}",
+                formatted_left_output("Verified", &OutputColor::Green)
+            ),
+            Verification::Missing(reason) if allow_unverified => eprintln!(
+                "{} {reason}; installing anyway (--allow-unverified)",
+                formatted_left_output("Warning", &OutputColor::Yellow)
+            ),
+            Verification::Missing(reason) => bail!(
+                "{reason}. Refusing to install an unverified binary; pass '--allow-unverified' \
+                to override"
+            ),
+        }
+    }
 
     Ok(())
 }
+
+/// Describes the checksum the file at `asset_path` was verified against
+async fn verify_checksum(
+    assets: &AssetSource<'_>,
+    target_asset: &str,
+    asset_path: &Path,
+) -> Result<Verification> {
+    let candidates = verify::checksum_asset_names(target_asset);
+    for name in &candidates {
+        if let Some(checksums) = assets.read_text(name).await? {
+            let sidecar = *name != verify::SHA256SUMS;
+            verify::verify_sha256(asset_path, &checksums, target_asset, sidecar)?;
+            return Ok(Verification::Passed(format!("'{name}'")));
+        }
+    }
+
+    if let Some(expected) = assets.listed_sha256(target_asset) {
+        verify::verify_sha256(
+            asset_path,
+            &format!("{expected}  {target_asset}"),
+            target_asset,
+            false,
+        )?;
+        return Ok(Verification::Passed(format!(
+            "the digest listed by {}",
+            assets.describe()
+        )));
+    }
+
+    Ok(Verification::Missing(format!(
+        "{} has no checksum for '{target_asset}' (looked for {} and a listed digest)",
+        assets.describe(),
+        candidates.join(", ")
+    )))
+}
+
+/// Describes the signature the file at `asset_path` was verified against
+async fn verify_signature(
+    assets: &AssetSource<'_>,
+    target_asset: &str,
+    asset_path: &Path,
+) -> Result<Verification> {
+    let Some(public_key) = verify::RELEASE_PUBLIC_KEY else {
+        return Ok(Verification::Missing(
+            "this installer was built without a release signing key (ZB_RELEASE_PUBKEY), so it \
+            can't check signatures"
+                .to_string(),
+        ));
+    };
+    let name = verify::signature_asset_name(target_asset);
+    let Some(signature) = assets.read_text(&name).await? else {
+        return Ok(Verification::Missing(format!(
+            "{} has no signature for '{target_asset}' (looked for {name})",
+            assets.describe()
+        )));
+    };
+
+    verify::verify_signature(asset_path, &signature, target_asset, public_key)?;
+
+    Ok(Verification::Passed(format!("'{name}'")))
+}
@@ -1,2 +1,8 @@
+pub mod download;
 pub mod install;
+pub mod self_update;
+pub mod source;
+pub mod state;
+pub mod store;
+pub mod verify;
 pub mod version;
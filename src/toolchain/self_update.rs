@@ -0,0 +1,199 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    system,
+    terminal::{formatted_left_output, message, OutputColor},
+};
+
+use super::{
+    download::{Downloader, NetworkOptions},
+    install::{verify_download, AssetSource},
+    source::ReleaseSource,
+    state,
+    store::STATE_DIR,
+    verify,
+};
+
+/// GitHub owner and repository the installer is published to, as install.sh downloads it,
+/// whichever channel zbcli is installed from
+pub const INSTALLER_REPO: (&str, &str) = ("zymbit-applications", "zb-bin-devel");
+
+/// Tag of the release the installer is published under. Its binary is the asset of the same name.
+pub const INSTALLER_TAG: &str = "installer";
+
+/// Asset of the installer release describing it
+pub const INSTALLER_METADATA: &str = "installer.json";
+
+/// How long the minimum version found by [`warn_if_outdated`] is trusted before it is looked up
+/// again, so the check doesn't cost API requests on every run
+const CHECK_INTERVAL: Duration = Duration::from_hours(24);
+
+/// The last minimum version check, kept in the state directory
+#[derive(Serialize, Deserialize)]
+struct VersionCheck {
+    /// In RFC 3339 UTC
+    checked_at: String,
+    minimum_version: Option<Version>,
+}
+
+impl VersionCheck {
+    fn path() -> PathBuf {
+        Path::new(STATE_DIR).join("installer-check.json")
+    }
+
+    /// The last check, unless it is too old to trust
+    fn load_recent() -> Option<Self> {
+        let check = state::read_json::<Self>(&Self::path()).ok()??;
+        let checked_at = humantime::parse_rfc3339(&check.checked_at).ok()?;
+        let age = SystemTime::now().duration_since(checked_at).ok()?;
+        (age < CHECK_INTERVAL).then_some(check)
+    }
+}
+
+/// Contents of [`INSTALLER_METADATA`]
+#[derive(Debug, Deserialize)]
+pub struct InstallerMetadata {
+    /// Version of the published installer
+    pub version: Version,
+
+    /// Oldest installer that still works with the published releases
+    #[serde(default)]
+    pub minimum_version: Option<Version>,
+}
+
+/// What a self-update did
+pub struct SelfUpdate {
+    pub from: Version,
+
+    /// The published version, if its release describes it
+    pub to: Option<Version>,
+    pub updated: bool,
+}
+
+/// This installer's version, pre-release part included
+///
+/// # Panics
+///
+/// Never: Cargo only accepts semver package versions.
+#[must_use]
+pub fn current_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("package version is valid semver")
+}
+
+async fn metadata(assets: &AssetSource<'_>) -> Result<Option<InstallerMetadata>> {
+    assets
+        .read_text(INSTALLER_METADATA)
+        .await?
+        .map(|text| {
+            serde_json::from_str(&text).context(format!("Malformed '{INSTALLER_METADATA}'"))
+        })
+        .transpose()
+}
+
+/// Warns if the installer release says this installer is too old to use. The answer is kept for
+/// a day. Any failure to find out is ignored, as it shouldn't get in the way of what the user
+/// asked for.
+pub async fn warn_if_outdated(source: &dyn ReleaseSource, network: &NetworkOptions) {
+    let minimum_version = if let Some(check) = VersionCheck::load_recent() {
+        check.minimum_version
+    } else {
+        let fetched = async {
+            let release = source.by_tag(INSTALLER_TAG).await.ok()?;
+            let downloader = Downloader::new(network).ok()?;
+            let metadata = metadata(&AssetSource::Release(&release, &downloader))
+                .await
+                .ok()?;
+            Some(metadata.and_then(|metadata| metadata.minimum_version))
+        }
+        .await;
+
+        // only a successful lookup is remembered, so a failed one is retried next run
+        let Some(minimum_version) = fetched else {
+            return;
+        };
+        let _ = state::write_json(
+            &VersionCheck::path(),
+            &VersionCheck {
+                checked_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                minimum_version: minimum_version.clone(),
+            },
+        );
+        minimum_version
+    };
+
+    let current = current_version();
+    if let Some(minimum_version) = minimum_version.filter(|min| current < *min) {
+        eprintln!(
+            "{} this installer ({current}) is older than the oldest supported version \
+            ({minimum_version}). Run 'zb-install self-update' to update it.",
+            formatted_left_output("Warning", &OutputColor::Yellow)
+        );
+    }
+}
+
+/// Replaces the running installer with the one published in `source`, unless that is the same
+/// version or binary. The download is verified like any other release asset.
+pub async fn self_update(
+    source: &dyn ReleaseSource,
+    network: &NetworkOptions,
+    allow_unverified: bool,
+) -> Result<SelfUpdate> {
+    let current = current_version();
+    let release = source
+        .by_tag(INSTALLER_TAG)
+        .await
+        .context("Failed to get the installer release")?;
+    let downloader = Downloader::new(network)?;
+    let assets = AssetSource::Release(&release, &downloader);
+
+    let published = metadata(&assets).await?.map(|metadata| metadata.version);
+    if published
+        .as_ref()
+        .is_some_and(|version| *version <= current)
+    {
+        return Ok(SelfUpdate {
+            from: current,
+            to: published,
+            updated: false,
+        });
+    }
+
+    let Some(asset_path) = assets.fetch(INSTALLER_TAG).await? else {
+        bail!("{} has no '{INSTALLER_TAG}' asset", source.describe());
+    };
+    let result: Result<bool> = async {
+        verify_download(&assets, INSTALLER_TAG, &asset_path, allow_unverified).await?;
+
+        let exe = env::current_exe().context("Failed to locate the running installer")?;
+        // a release without metadata can still be recognised as the one already running
+        if verify::sha256_file(&asset_path)? == verify::sha256_file(&exe)? {
+            return Ok(false);
+        }
+
+        message!("Replacing {}", exe.display());
+        let mut asset_file = fs::File::open(&asset_path)
+            .context(format!("Failed to open ({})", asset_path.display()))?;
+        system::replace_file_atomically(&exe, &mut asset_file, true)?;
+        Ok(true)
+    }
+    .await;
+
+    let _ = fs::remove_file(&asset_path);
+    if let Some(staged_dir) = asset_path.parent() {
+        let _ = fs::remove_dir(staged_dir);
+    }
+
+    Ok(SelfUpdate {
+        from: current,
+        to: published,
+        updated: result?,
+    })
+}
@@ -0,0 +1,553 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use derive_more::Display;
+use reqwest::{header::HeaderMap, Client, StatusCode, Url};
+use serde::Deserialize;
+use urlencoding::encode;
+
+use super::download::{http_client, NetworkOptions};
+
+/// Zymbit release repositories
+#[derive(Display, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Channel {
+    #[default]
+    #[display(fmt = "stable")]
+    Stable,
+
+    #[display(fmt = "devel")]
+    Devel,
+}
+
+impl Channel {
+    /// GitHub owner and repository the channel is published to
+    #[must_use]
+    pub fn repo(self) -> (&'static str, &'static str) {
+        match self {
+            Channel::Stable => ("zymbit-applications", "zb-bin"),
+            Channel::Devel => ("zymbit-applications", "zb-bin-devel"),
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "stable" => Ok(Channel::Stable),
+            "devel" => Ok(Channel::Devel),
+            _ => bail!("unknown channel '{s}', expected 'stable' or 'devel'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag: String,
+
+    #[serde(default)]
+    pub prerelease: bool,
+
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Asset {
+    pub name: String,
+
+    /// Absolute download URL
+    pub url: String,
+
+    /// Lowercase hex SHA-256 digest, if the source publishes one
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl Release {
+    #[must_use]
+    pub fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+/// Somewhere releases of the Zymbit tools are published
+#[async_trait]
+pub trait ReleaseSource: Send + Sync {
+    /// Where releases come from, for messages
+    fn describe(&self) -> String;
+
+    /// What installs from this source are recorded as coming from: the channel name, or the
+    /// repository or URL when it isn't a Zymbit channel
+    fn channel(&self) -> String;
+
+    /// The newest release that isn't a pre-release
+    async fn latest(&self) -> Result<Release>;
+
+    async fn by_tag(&self, tag: &str) -> Result<Release>;
+
+    /// One page of releases, newest first. `page` starts at 1.
+    async fn list(&self, page: u32, per_page: u8) -> Result<Vec<Release>>;
+}
+
+/// Splits an `owner/name` repository spec
+pub fn parse_repo_spec(spec: &str) -> Result<(&str, &str)> {
+    match spec.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+            Ok((owner, repo))
+        }
+        _ => bail!("invalid repository '{spec}', expected 'owner/name'"),
+    }
+}
+
+/// Releases of a GitHub repository, read through the REST API
+pub struct GithubSource {
+    client: Client,
+    owner: String,
+    repo: String,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    assets: Vec<GithubAsset>,
+}
+
+#[derive(Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl From<GithubRelease> for Release {
+    fn from(release: GithubRelease) -> Self {
+        Self {
+            tag: release.tag_name,
+            prerelease: release.prerelease,
+            assets: release
+                .assets
+                .into_iter()
+                .map(|asset| Asset {
+                    name: asset.name,
+                    url: asset.browser_download_url,
+                    sha256: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl GithubSource {
+    pub fn new(owner: &str, repo: &str, options: &NetworkOptions) -> Result<Self> {
+        Ok(Self {
+            client: http_client(options)?,
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            token: None,
+        })
+    }
+
+    /// Authenticates API requests with `token`, which raises GitHub's rate limit from 60 to
+    /// 5000 requests per hour
+    #[must_use]
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token.filter(|token| !token.is_empty());
+        self
+    }
+
+    pub fn for_channel(channel: Channel, options: &NetworkOptions) -> Result<Self> {
+        let (owner, repo) = channel.repo();
+        Self::new(owner, repo, options)
+    }
+
+    /// `spec` is `owner/name`, e.g. a fork of one of the channel repositories
+    pub fn from_spec(spec: &str, options: &NetworkOptions) -> Result<Self> {
+        let (owner, repo) = parse_repo_spec(spec)?;
+        Self::new(owner, repo, options)
+    }
+
+    /// `path` is relative to the repository's `releases` endpoint
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<Option<T>> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/releases{path}",
+            self.owner, self.repo
+        );
+        let mut req = self
+            .client
+            .get(&url)
+            .header("Accept", "application/vnd.github+json");
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+        let res = req
+            .send()
+            .await
+            .context(format!("Failed to reach GitHub ({url})"))?;
+
+        match res.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            StatusCode::UNAUTHORIZED => bail!(
+                "GitHub rejected the API token ({url}). Check GITHUB_TOKEN or --github-token."
+            ),
+            StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS => {
+                if let Some(message) = rate_limit_message(res.headers(), self.token.is_some()) {
+                    bail!("{message}");
+                }
+            }
+            _ => {}
+        }
+        let body = res
+            .error_for_status()
+            .context(format!("GitHub request failed ({url})"))?
+            .bytes()
+            .await
+            .context(format!("Failed to read GitHub response ({url})"))?;
+
+        serde_json::from_slice(&body)
+            .map(Some)
+            .context(format!("Unexpected GitHub response ({url})"))
+    }
+}
+
+/// Explains a rate-limited GitHub response from its `x-ratelimit-*` or `retry-after` headers.
+/// Returns `None` if the response wasn't caused by rate limiting.
+fn rate_limit_message(headers: &HeaderMap, authenticated: bool) -> Option<String> {
+    let header = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+
+    let wait_secs = if let Some(retry_after) = header("retry-after") {
+        retry_after
+    } else if header("x-ratelimit-remaining") == Some(0) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        header("x-ratelimit-reset")?.saturating_sub(now)
+    } else {
+        return None;
+    };
+
+    let limit = header("x-ratelimit-limit").map_or_else(String::new, |limit| {
+        format!(" of {limit} requests per hour")
+    });
+    let hint = if authenticated {
+        ""
+    } else {
+        " Set GITHUB_TOKEN or pass --github-token to raise the limit."
+    };
+
+    Some(format!(
+        "GitHub API rate limit{limit} exceeded. It resets in {} min {} s.{hint}",
+        wait_secs / 60,
+        wait_secs % 60
+    ))
+}
+
+#[async_trait]
+impl ReleaseSource for GithubSource {
+    fn describe(&self) -> String {
+        format!("github.com/{}/{}", self.owner, self.repo)
+    }
+
+    fn channel(&self) -> String {
+        [Channel::Stable, Channel::Devel]
+            .into_iter()
+            .find(|channel| channel.repo() == (self.owner.as_str(), self.repo.as_str()))
+            .map_or_else(
+                || format!("{}/{}", self.owner, self.repo),
+                |channel| channel.to_string(),
+            )
+    }
+
+    async fn latest(&self) -> Result<Release> {
+        match self.get::<GithubRelease>("/latest").await? {
+            Some(release) => Ok(release.into()),
+            None => bail!("{} has no releases", self.describe()),
+        }
+    }
+
+    async fn by_tag(&self, tag: &str) -> Result<Release> {
+        match self
+            .get::<GithubRelease>(&format!("/tags/{}", encode(tag)))
+            .await?
+        {
+            Some(release) => Ok(release.into()),
+            None => bail!("{} has no release tagged '{tag}'", self.describe()),
+        }
+    }
+
+    async fn list(&self, page: u32, per_page: u8) -> Result<Vec<Release>> {
+        Ok(self
+            .get::<Vec<GithubRelease>>(&format!("?per_page={per_page}&page={page}"))
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .map(Release::from)
+            .collect())
+    }
+}
+
+/// Releases described by an `index.json` file on a plain HTTP server:
+///
+/// ```json
+/// { "releases": [ { "tag": "zbcli-1.2.3", "prerelease": false,
+///     "assets": [ { "name": "zbcli-rpi5", "url": "zbcli-1.2.3/zbcli-rpi5", "sha256": "..." } ] } ] }
+/// ```
+///
+/// Releases are listed newest first. Asset URLs may be relative to the index.
+pub struct HttpIndexSource {
+    client: Client,
+    index_url: Url,
+}
+
+#[derive(Deserialize)]
+struct ReleaseIndex {
+    releases: Vec<Release>,
+}
+
+impl HttpIndexSource {
+    /// `base_url` is the directory holding `index.json`
+    pub fn new(base_url: &str, options: &NetworkOptions) -> Result<Self> {
+        let base_url = if base_url.ends_with('/') {
+            base_url.to_string()
+        } else {
+            format!("{base_url}/")
+        };
+        let index_url = Url::parse(&base_url)
+            .and_then(|base| base.join("index.json"))
+            .context(format!("Invalid release index URL '{base_url}'"))?;
+
+        Ok(Self {
+            client: http_client(options)?,
+            index_url,
+        })
+    }
+
+    async fn releases(&self) -> Result<Vec<Release>> {
+        let body = self
+            .client
+            .get(self.index_url.clone())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context(format!("Failed to get release index ({})", self.index_url))?
+            .bytes()
+            .await
+            .context(format!("Failed to read release index ({})", self.index_url))?;
+
+        self.parse_index(&body)
+    }
+
+    /// Reads the releases out of an `index.json` body, resolving asset URLs against the index
+    fn parse_index(&self, body: &[u8]) -> Result<Vec<Release>> {
+        let index: ReleaseIndex = serde_json::from_slice(body)
+            .context(format!("Malformed release index ({})", self.index_url))?;
+
+        index
+            .releases
+            .into_iter()
+            .map(|mut release| {
+                for asset in &mut release.assets {
+                    asset.url = self
+                        .index_url
+                        .join(&asset.url)
+                        .context(format!("Invalid URL for asset '{}'", asset.name))?
+                        .to_string();
+                }
+                Ok(release)
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ReleaseSource for HttpIndexSource {
+    fn describe(&self) -> String {
+        self.index_url.to_string()
+    }
+
+    fn channel(&self) -> String {
+        self.describe()
+    }
+
+    async fn latest(&self) -> Result<Release> {
+        match self
+            .releases()
+            .await?
+            .into_iter()
+            .find(|release| !release.prerelease)
+        {
+            Some(release) => Ok(release),
+            None => bail!("{} lists no releases", self.describe()),
+        }
+    }
+
+    async fn by_tag(&self, tag: &str) -> Result<Release> {
+        match self
+            .releases()
+            .await?
+            .into_iter()
+            .find(|release| release.tag == tag)
+        {
+            Some(release) => Ok(release),
+            None => bail!("{} has no release tagged '{tag}'", self.describe()),
+        }
+    }
+
+    async fn list(&self, page: u32, per_page: u8) -> Result<Vec<Release>> {
+        let skip = (page.saturating_sub(1) as usize) * usize::from(per_page);
+
+        Ok(self
+            .releases()
+            .await?
+            .into_iter()
+            .skip(skip)
+            .take(usize::from(per_page))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const INDEX: &str = r#"{ "releases": [
+        { "tag": "zbcli-1.3.0-rc.1", "prerelease": true, "assets": [] },
+        { "tag": "zbcli-1.2.3", "assets": [
+            { "name": "zbcli-rpi5", "url": "zbcli-1.2.3/zbcli-rpi5", "sha256": "ab12" },
+            { "name": "zbcli-rpi4", "url": "/mirror/zbcli-rpi4" },
+            { "name": "zbcli-rpi4.minisig", "url": "https://cdn.example.com/zbcli-rpi4.minisig" }
+        ] },
+        { "tag": "zbcli-1.2.0" },
+        { "tag": "installer" }
+    ] }"#;
+
+    fn source(base_url: &str) -> HttpIndexSource {
+        HttpIndexSource::new(base_url, &NetworkOptions::default()).unwrap()
+    }
+
+    /// Serves `body` as the response to every request, returning the server's base URL
+    async fn serve(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        format!("http://{address}/releases")
+    }
+
+    #[test]
+    fn parses_index() {
+        let releases = source("https://example.com/zb")
+            .parse_index(INDEX.as_bytes())
+            .unwrap();
+
+        let tags: Vec<_> = releases
+            .iter()
+            .map(|release| release.tag.as_str())
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                "zbcli-1.3.0-rc.1",
+                "zbcli-1.2.3",
+                "zbcli-1.2.0",
+                "installer"
+            ]
+        );
+        assert!(releases[0].prerelease);
+        assert!(!releases[1].prerelease);
+        assert!(releases[2].assets.is_empty());
+        assert_eq!(
+            releases[1].asset("zbcli-rpi5").unwrap().sha256.as_deref(),
+            Some("ab12")
+        );
+        assert_eq!(releases[1].asset("zbcli-rpi4").unwrap().sha256, None);
+    }
+
+    #[test]
+    fn resolves_asset_urls_against_index() {
+        let releases = source("https://example.com/zb/")
+            .parse_index(INDEX.as_bytes())
+            .unwrap();
+
+        let url = |name| releases[1].asset(name).unwrap().url.as_str();
+        assert_eq!(
+            url("zbcli-rpi5"),
+            "https://example.com/zb/zbcli-1.2.3/zbcli-rpi5"
+        );
+        assert_eq!(url("zbcli-rpi4"), "https://example.com/mirror/zbcli-rpi4");
+        assert_eq!(
+            url("zbcli-rpi4.minisig"),
+            "https://cdn.example.com/zbcli-rpi4.minisig"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_index() {
+        let source = source("https://example.com/zb");
+        assert!(source.parse_index(b"{ \"releases\": {} }").is_err());
+        assert!(source.parse_index(b"<html></html>").is_err());
+    }
+
+    #[tokio::test]
+    async fn lists_pages_of_served_index() {
+        let source = source(&serve(INDEX).await);
+
+        let page = |page| {
+            let source = &source;
+            async move {
+                source
+                    .list(page, 3)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|release| release.tag)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            page(1).await,
+            ["zbcli-1.3.0-rc.1", "zbcli-1.2.3", "zbcli-1.2.0"]
+        );
+        assert_eq!(page(2).await, ["installer"]);
+        assert!(page(3).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn finds_latest_and_tagged_releases() {
+        let source = source(&serve(INDEX).await);
+
+        assert_eq!(source.latest().await.unwrap().tag, "zbcli-1.2.3");
+        assert_eq!(source.by_tag("installer").await.unwrap().tag, "installer");
+        assert!(source.by_tag("zbcli-9.9.9").await.is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{system, zbcli::Signing};
+
+use super::{install::Installed, store::STATE_DIR};
+
+/// Everything known about an installed tool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallRecord {
+    #[serde(flatten)]
+    pub installed: Installed,
+
+    pub signing: Signing,
+
+    /// Version of the installer that installed it
+    pub installer_version: String,
+
+    /// When it was installed or rolled back to, in RFC 3339 UTC
+    pub installed_at: String,
+}
+
+impl InstallRecord {
+    #[must_use]
+    pub fn new(installed: Installed, signing: Signing) -> Self {
+        Self {
+            installed,
+            signing,
+            installer_version: env!("CARGO_PKG_VERSION").to_string(),
+            installed_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        }
+    }
+}
+
+/// `state.json` in the installer's state directory: what is installed on this system, by tool.
+///
+/// A copy of each record is also kept beside the binary in the version store, so rolling back
+/// restores it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateManifest {
+    #[serde(default)]
+    pub tools: BTreeMap<String, InstallRecord>,
+}
+
+impl StateManifest {
+    #[must_use]
+    pub fn path() -> PathBuf {
+        Path::new(STATE_DIR).join("state.json")
+    }
+
+    /// Reads the manifest, which is empty if nothing was installed yet
+    pub fn load() -> Result<Self> {
+        read_json(&Self::path()).map(Option::unwrap_or_default)
+    }
+
+    #[must_use]
+    pub fn get(&self, tool: &str) -> Option<&InstallRecord> {
+        self.tools.get(tool)
+    }
+
+    fn save(&self) -> Result<()> {
+        write_json(&Self::path(), self)
+    }
+}
+
+/// Records that `record` was just installed, both in the manifest and in the version store
+pub fn record_install(record: &InstallRecord, stored_copy: &Path) -> Result<()> {
+    if let Some(dir) = stored_copy.parent().filter(|dir| dir.is_dir()) {
+        write_json(&dir.join("install.json"), record)?;
+    }
+
+    let mut manifest = StateManifest::load()?;
+    manifest
+        .tools
+        .insert(record.installed.tool.clone(), record.clone());
+    manifest.save()
+}
+
+/// Records that the stored copy of `tool` at `stored_copy` was restored. If the version store
+/// holds no record for it, `tool` is dropped from the manifest since what's installed is unknown.
+pub fn record_rollback(tool: &str, stored_copy: &Path) -> Result<()> {
+    let record = match stored_copy.parent() {
+        Some(dir) => read_json::<InstallRecord>(&dir.join("install.json"))?,
+        None => None,
+    };
+
+    let mut manifest = StateManifest::load()?;
+    match record {
+        Some(record) => {
+            let record = InstallRecord::new(record.installed, record.signing);
+            manifest.tools.insert(tool.to_string(), record);
+        }
+        None => {
+            manifest.tools.remove(tool);
+        }
+    }
+    manifest.save()
+}
+
+/// Drops `tool` from the manifest after it was removed
+pub fn record_uninstall(tool: &str) -> Result<()> {
+    let mut manifest = StateManifest::load()?;
+    if manifest.tools.remove(tool).is_some() {
+        manifest.save()?;
+    }
+    Ok(())
+}
+
+pub(super) fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Option<T>> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to read ({})", path.display())),
+    };
+
+    serde_json::from_slice(&content)
+        .map(Some)
+        .context(format!("Malformed install state ({})", path.display()))
+}
+
+pub(super) fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create ({})", dir.display()))?;
+    }
+    let mut content =
+        serde_json::to_vec_pretty(value).context("Failed to serialize install state")?;
+    content.push(b'\n');
+
+    system::replace_file_atomically(path, &mut content.as_slice(), false)
+}
@@ -0,0 +1,294 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::system;
+
+use super::verify;
+
+/// Root of the installer's persistent state
+pub const STATE_DIR: &str = "/var/lib/zb-installer";
+
+/// Keeps a copy of every installed binary under `versions/<tag>/<tool>`, with the channel it came
+/// from in `versions/<tag>/channel`, along with a per-tool history of installed tags, most recent
+/// last, and the tag currently installed, so an earlier version can be restored offline.
+///
+/// Only the [`VersionStore::RETAINED`] most recently installed versions of a tool are kept, plus
+/// the current one, untracked copies included.
+pub struct VersionStore {
+    root: PathBuf,
+}
+
+impl VersionStore {
+    /// Number of most recently installed versions kept per tool
+    pub const RETAINED: usize = 5;
+
+    #[must_use]
+    pub fn open() -> Self {
+        Self::at(Path::new(STATE_DIR).join("versions"))
+    }
+
+    fn at(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    #[must_use]
+    pub fn binary_path(&self, tool: &str, tag: &str) -> PathBuf {
+        self.root.join(tag).join(tool)
+    }
+
+    /// Installed tags for `tool` that still have a stored binary, oldest first
+    pub fn history(&self, tool: &str) -> Result<Vec<String>> {
+        let history_path = self.history_path(tool);
+        let history = match fs::read_to_string(&history_path) {
+            Ok(history) => history,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).context(format!("Failed to read ({})", history_path.display()))
+            }
+        };
+
+        Ok(history
+            .lines()
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty() && self.binary_path(tool, tag).is_file())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// The tag `tool` was last installed or rolled back to, if known
+    pub fn current(&self, tool: &str) -> Result<Option<String>> {
+        let current = fs::read_to_string(self.current_path(tool)).unwrap_or_default();
+        let current = current.trim();
+
+        Ok(self.history(tool)?.into_iter().find(|tag| tag == current))
+    }
+
+    /// The channel the stored `tag` was installed from, if recorded
+    #[must_use]
+    pub fn channel(&self, tag: &str) -> Option<String> {
+        fs::read_to_string(self.root.join(tag).join("channel"))
+            .ok()
+            .map(|channel| channel.trim().to_string())
+    }
+
+    /// Stores a copy of the file at `binary` as `tag`, installed from `channel`, and records it as
+    /// the current version of `tool`
+    pub fn save(&self, tool: &str, tag: &str, channel: &str, binary: &Path) -> Result<()> {
+        if tag.is_empty() || tag.contains('/') || tag.starts_with('.') {
+            bail!("invalid version tag '{tag}'");
+        }
+
+        let tag_dir = self.root.join(tag);
+        fs::create_dir_all(&tag_dir)
+            .context(format!("Failed to create ({})", tag_dir.display()))?;
+        let mut binary_file =
+            File::open(binary).context(format!("Failed to open ({})", binary.display()))?;
+        system::replace_file_atomically(&self.binary_path(tool, tag), &mut binary_file, true)?;
+        system::replace_file_atomically(
+            &tag_dir.join("channel"),
+            &mut format!("{channel}\n").as_bytes(),
+            false,
+        )?;
+
+        let mut history = self.history(tool)?;
+        history.retain(|t| t != tag);
+        history.push(tag.to_string());
+        self.write_history(tool, &history)?;
+        self.write_current(tool, tag)?;
+        self.prune(tool)
+    }
+
+    /// Deletes the stored versions of `tool` beyond the [`VersionStore::RETAINED`] most recent,
+    /// keeping the current one
+    fn prune(&self, tool: &str) -> Result<()> {
+        let history = self.history(tool)?;
+        let Some(excess) = history.len().checked_sub(Self::RETAINED) else {
+            return Ok(());
+        };
+        let current = self.current(tool)?;
+
+        let (pruned, kept): (Vec<_>, Vec<_>) = history
+            .into_iter()
+            .enumerate()
+            .partition(|(i, tag)| *i < excess && current.as_ref() != Some(tag));
+        for (_, tag) in &pruned {
+            let tag_dir = self.root.join(tag);
+            fs::remove_dir_all(&tag_dir)
+                .context(format!("Failed to remove ({})", tag_dir.display()))?;
+        }
+
+        let kept = kept.into_iter().map(|(_, tag)| tag).collect::<Vec<_>>();
+        self.write_history(tool, &kept)
+    }
+
+    /// Keeps `installed` if the store doesn't already hold it as the current version. It is
+    /// recorded under an `untracked-<unix time>` tag since its release is unknown.
+    pub fn preserve_untracked(&self, tool: &str, installed: &Path) -> Result<()> {
+        if !installed.is_file() {
+            return Ok(());
+        }
+
+        if let Some(current) = self.current(tool)? {
+            let stored = verify::sha256_file(&self.binary_path(tool, &current))?;
+            if stored == verify::sha256_file(installed)? {
+                return Ok(());
+            }
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.save(tool, &format!("untracked-{now}"), "unknown", installed)
+    }
+
+    /// Installs the stored binary for `tag`, or the version installed before the current one, to
+    /// `install_path`. Returns the restored tag.
+    pub fn rollback(&self, tool: &str, tag: Option<&str>, install_path: &Path) -> Result<String> {
+        let history = self.history(tool)?;
+
+        let target = if let Some(tag) = tag {
+            history
+                .iter()
+                .find(|t| *t == tag)
+                .cloned()
+                .with_context(|| {
+                    format!(
+                        "no stored version '{tag}' of {tool}. Available: {}",
+                        describe(&history)
+                    )
+                })?
+        } else {
+            let current = self.current(tool)?;
+            let current_idx = current
+                .and_then(|current| history.iter().position(|t| *t == current))
+                .unwrap_or(history.len());
+            let Some(previous) = current_idx.checked_sub(1) else {
+                bail!(
+                    "no version of {tool} older than the current one is stored. Available: {}",
+                    describe(&history)
+                );
+            };
+            history[previous].clone()
+        };
+
+        let stored = self.binary_path(tool, &target);
+        let mut stored_file =
+            File::open(&stored).context(format!("Failed to open ({})", stored.display()))?;
+        system::replace_file_atomically(install_path, &mut stored_file, true)?;
+
+        self.write_current(tool, &target)?;
+
+        Ok(target)
+    }
+
+    /// Forgets which version of `tool` is installed, e.g. after it was removed. Stored versions
+    /// are kept, so the most recent one can still be rolled back to.
+    pub fn forget_current(&self, tool: &str) -> Result<()> {
+        let current_path = self.current_path(tool);
+        match fs::remove_file(&current_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).context(format!("Failed to remove ({})", current_path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes every stored version of `tool` along with its history
+    pub fn purge(&self, tool: &str) -> Result<()> {
+        for tag in self.history(tool)? {
+            let tag_dir = self.root.join(&tag);
+            fs::remove_dir_all(&tag_dir)
+                .context(format!("Failed to remove ({})", tag_dir.display()))?;
+        }
+
+        for path in [self.history_path(tool), self.current_path(tool)] {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).context(format!("Failed to remove ({})", path.display()))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn history_path(&self, tool: &str) -> PathBuf {
+        self.root.join(format!("{tool}.history"))
+    }
+
+    fn current_path(&self, tool: &str) -> PathBuf {
+        self.root.join(format!("{tool}.current"))
+    }
+
+    fn write_current(&self, tool: &str, tag: &str) -> Result<()> {
+        let content = format!("{tag}\n");
+        system::replace_file_atomically(&self.current_path(tool), &mut content.as_bytes(), false)
+    }
+
+    fn write_history(&self, tool: &str, history: &[String]) -> Result<()> {
+        let mut content = history.join("\n");
+        content.push('\n');
+        system::replace_file_atomically(&self.history_path(tool), &mut content.as_bytes(), false)
+    }
+}
+
+fn describe(history: &[String]) -> String {
+    if history.is_empty() {
+        "none".to_string()
+    } else {
+        history.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_recent_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("zbcli");
+        fs::write(&binary, "zbcli").unwrap();
+        let store = VersionStore::at(dir.path().join("versions"));
+
+        let tags = (0..8).map(|i| format!("zbcli-1.{i}.0")).collect::<Vec<_>>();
+        for tag in &tags {
+            store.save("zbcli", tag, "stable", &binary).unwrap();
+        }
+
+        let retained = &tags[tags.len() - VersionStore::RETAINED..];
+        assert_eq!(store.history("zbcli").unwrap(), retained);
+        assert_eq!(store.current("zbcli").unwrap().as_ref(), tags.last());
+        assert!(!dir.path().join("versions/zbcli-1.2.0").exists());
+        assert!(store.binary_path("zbcli", &retained[0]).is_file());
+    }
+
+    #[test]
+    fn untracked_copies_count_towards_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("zbcli");
+        fs::write(&binary, "zbcli").unwrap();
+        let store = VersionStore::at(dir.path().join("versions"));
+        for i in 0..VersionStore::RETAINED {
+            store
+                .save("zbcli", &format!("zbcli-1.{i}.0"), "stable", &binary)
+                .unwrap();
+        }
+
+        let installed = dir.path().join("installed");
+        fs::write(&installed, "zbcli built locally").unwrap();
+        store.preserve_untracked("zbcli", &installed).unwrap();
+
+        let history = store.history("zbcli").unwrap();
+        assert_eq!(history.len(), VersionStore::RETAINED);
+        assert_eq!(history[0], "zbcli-1.1.0");
+        assert!(history.last().unwrap().starts_with("untracked-"));
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use minisign_verify::{PublicKey, Signature};
+use sha2::{Digest, Sha256};
+
+/// Name of the combined checksum asset, in `sha256sum` output format
+pub const SHA256SUMS: &str = "SHA256SUMS";
+
+/// Minisign public key that Zymbit release assets are signed with, embedded from
+/// `ZB_RELEASE_PUBKEY` at build time. The release workflow sets it from the repository variable of
+/// the same name; the matching secret key is the `MINISIGN_SECRET_KEY` secret, as described under
+/// "Release signing" in the README. Builds without a key can't check signatures.
+pub const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("ZB_RELEASE_PUBKEY");
+
+/// Names of the assets that may carry the checksum for `asset`, most specific first
+#[must_use]
+pub fn checksum_asset_names(asset: &str) -> [String; 2] {
+    [format!("{asset}.sha256"), SHA256SUMS.to_string()]
+}
+
+/// Name of the detached minisign signature asset for `asset`
+#[must_use]
+pub fn signature_asset_name(asset: &str) -> String {
+    format!("{asset}.minisig")
+}
+
+/// Returns the lowercase hex SHA-256 digest of the file at `path`
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file = File::open(path).context(format!("Failed to open ({})", path.display()))?;
+    io::copy(&mut file, &mut hasher).context(format!("Failed to read ({})", path.display()))?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Finds the expected digest for `asset` within a checksum file.
+///
+/// Accepts `sha256sum` output (`<digest>  <name>` or `<digest> *<name>` per line). A line holding
+/// only a digest is accepted as well in a `sidecar`, the `<asset>.sha256` file, since those are
+/// often written that way; in a combined file it could belong to any asset.
+pub fn expected_sha256(checksums: &str, asset: &str, sidecar: bool) -> Result<String> {
+    for line in checksums.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(digest) = fields.next() else {
+            continue;
+        };
+
+        let matches = match fields.next() {
+            Some(name) => name.trim_start_matches('*') == asset,
+            None => sidecar,
+        };
+        if !matches {
+            continue;
+        }
+
+        if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("malformed SHA-256 digest for '{asset}': '{digest}'");
+        }
+
+        return Ok(digest.to_ascii_lowercase());
+    }
+
+    bail!("no SHA-256 digest listed for '{asset}'")
+}
+
+/// Checks the file at `path` against the digest listed for `asset` in `checksums`, which is the
+/// `<asset>.sha256` file if `sidecar` is set
+pub fn verify_sha256(path: &Path, checksums: &str, asset: &str, sidecar: bool) -> Result<()> {
+    let expected = expected_sha256(checksums, asset, sidecar)
+        .context(format!("Failed to read checksum for '{asset}'"))?;
+    let actual = sha256_file(path)?;
+
+    if actual != expected {
+        bail!(
+            "checksum mismatch for '{asset}': expected {expected}, got {actual}. \
+            The download may be truncated or tampered with."
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks the file at `path` against a detached minisign `signature` made with the secret key
+/// for `public_key`, such as [`RELEASE_PUBLIC_KEY`].
+///
+/// Only prehashed signatures (the `minisign` default) are accepted, which lets the file be
+/// checked without reading it into memory.
+pub fn verify_signature(path: &Path, signature: &str, asset: &str, public_key: &str) -> Result<()> {
+    let public_key = PublicKey::from_base64(public_key)
+        .context("Failed to decode the embedded release public key")?;
+    let signature = Signature::decode(signature)
+        .context(format!("Failed to decode signature for '{asset}'"))?;
+    let failed = || {
+        format!(
+            "signature check failed for '{asset}'. The download may be tampered with or signed \
+            by an unknown key."
+        )
+    };
+
+    let mut verifier = public_key.verify_stream(&signature).with_context(failed)?;
+    let mut file = File::open(path).context(format!("Failed to open ({})", path.display()))?;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .context(format!("Failed to read ({})", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        verifier.update(&buf[..n]);
+    }
+
+    verifier.finalize().with_context(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn reads_sha256sum_lines() {
+        let checksums = format!("{}  zbcli-rpi4\n{DIGEST}  zbcli-rpi5\n", "0".repeat(64));
+        assert_eq!(
+            expected_sha256(&checksums, "zbcli-rpi5", false).unwrap(),
+            DIGEST
+        );
+
+        let binary_mode = format!("{}  *zbcli-rpi5\n", DIGEST.to_uppercase());
+        assert_eq!(
+            expected_sha256(&binary_mode, "zbcli-rpi5", false).unwrap(),
+            DIGEST
+        );
+    }
+
+    #[test]
+    fn accepts_bare_digest_only_in_sidecar() {
+        let bare = format!("{DIGEST}\n");
+        assert_eq!(expected_sha256(&bare, "zbcli-rpi5", true).unwrap(), DIGEST);
+        assert!(expected_sha256(&bare, "zbcli-rpi5", false).is_err());
+
+        // a stray digest in SHA256SUMS isn't taken for the asset's
+        let combined = format!("{DIGEST}\n{}  zbcli-rpi5\n", "1".repeat(64));
+        assert_eq!(
+            expected_sha256(&combined, "zbcli-rpi5", false).unwrap(),
+            "1".repeat(64)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_or_missing_digest() {
+        let error = expected_sha256("abc123  zbcli-rpi5\n", "zbcli-rpi5", false).unwrap_err();
+        assert!(error.to_string().starts_with("malformed SHA-256 digest"));
+
+        let error =
+            expected_sha256(&format!("{DIGEST}  zbcli-rpi4\n"), "zbcli-rpi5", false).unwrap_err();
+        assert!(error.to_string().starts_with("no SHA-256 digest listed"));
+    }
+
+    #[test]
+    fn checks_file_against_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("zbcli-rpi5");
+        std::fs::write(&path, "hello").unwrap();
+
+        assert_eq!(sha256_file(&path).unwrap(), DIGEST);
+        verify_sha256(
+            &path,
+            &format!("{DIGEST}  zbcli-rpi5\n"),
+            "zbcli-rpi5",
+            false,
+        )
+        .unwrap();
+
+        std::fs::write(&path, "hello, tampered").unwrap();
+        let error = verify_sha256(&path, DIGEST, "zbcli-rpi5", true).unwrap_err();
+        assert!(
+            error.to_string().starts_with("checksum mismatch"),
+            "{error}"
+        );
+    }
+
+    /// A throwaway minisign key pair made for these tests, and the asset it signed
+    const TEST_PUBLIC_KEY: &str = "RWT/yYnwttcK8GeEyNEdSzrFPFYlNmJF429wXhvfNFJYtFZu3vuBYzN/";
+    const SIGNED_ASSET: &str = "zbcli test asset\n";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUT/yYnwttcK8LT/2pSiL5jApZJ3JGxSrVqwXAQs2UdX6xe4O5S1SDS8J+wzwjjRTALngYF6Mc+vElv9glYldrzg+83ahbQ3UwA=
+trusted comment: timestamp:1760000000\tfile:zbcli-rpi5\tprehashed
+bUHc5KoxJrqX6sYNX1F3R5I5/l1u0yKbWr2+ukVuCmn6W5MH3wWv0J9t7QAgviWuv3LbVHNvahGNbm65KBg4Ag==
+";
+
+    /// The same asset signed with a second, unrelated test key
+    const OTHER_KEY_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUSEMMnK2tOo18P1xecfhB/Uusj3M0Je+7K2+B6VGcve4lnllTlcKL6cvnCvWWQf6VkgTvXIXiXxgOcMTDC08mK3HzTXTmuaNAk=
+trusted comment: timestamp:1760000000\tfile:zbcli-rpi5\tprehashed
+I0FTL8L09TWPmpgK/6WmJdtL4d6jvSE5O8+O9vbwWGUXE/bNnmS9aoN1NHeA+pS2+mTESaHttRb/0Bh7d0ugAw==
+";
+
+    fn asset(content: &str) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("zbcli-rpi5");
+        std::fs::write(&path, content).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let (_dir, path) = asset(SIGNED_ASSET);
+        verify_signature(&path, SIGNATURE, "zbcli-rpi5", TEST_PUBLIC_KEY).unwrap();
+    }
+
+    #[test]
+    fn rejects_tampered_file() {
+        let (_dir, path) = asset("zbcli test asset, tampered\n");
+        let error = verify_signature(&path, SIGNATURE, "zbcli-rpi5", TEST_PUBLIC_KEY).unwrap_err();
+        assert!(
+            error.to_string().starts_with("signature check failed"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn rejects_signature_from_another_key() {
+        let (_dir, path) = asset(SIGNED_ASSET);
+        let error = verify_signature(&path, OTHER_KEY_SIGNATURE, "zbcli-rpi5", TEST_PUBLIC_KEY)
+            .unwrap_err();
+        assert!(
+            error.to_string().starts_with("signature check failed"),
+            "{error}"
+        );
+    }
+}
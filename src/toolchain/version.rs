@@ -1,70 +1,432 @@
-use anyhow::{Context, Result};
-use octocrab::models::repos::Release;
-use urlencoding::encode;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use derive_more::Display;
+use semver::{Prerelease, Version, VersionReq};
+
+use super::source::{Release, ReleaseSource};
+
+/// Which release `--zb-version` asks for
+#[derive(Display, Debug, Clone)]
+pub enum VersionSpec {
+    /// The newest release that isn't a pre-release
+    #[display(fmt = "latest")]
+    Latest,
+
+    /// The release with exactly this tag, e.g. `zbcli-1.2.3`
+    #[display(fmt = "{_0}")]
+    Tag(String),
+
+    /// The newest release whose version matches, e.g. `~1.2` or `>=1.1, <2`
+    #[display(fmt = "{_0}")]
+    Constraint(VersionReq),
+}
+
+impl FromStr for VersionSpec {
+    type Err = anyhow::Error;
+
+    /// A bare version pins every component it names, so `1.2` means any 1.2.x rather than
+    /// semver's default of `^1.2`. Anything that doesn't look like a version is taken as a tag.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("latest") {
+            return Ok(VersionSpec::Latest);
+        }
+
+        let req = if s.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("={s}")
+        } else if s.starts_with(['=', '>', '<', '~', '^', '*']) {
+            s.to_string()
+        } else {
+            return Ok(VersionSpec::Tag(s.to_string()));
+        };
+
+        VersionReq::parse(&req)
+            .map(VersionSpec::Constraint)
+            .context(format!("invalid version constraint '{s}'"))
+    }
+}
+
+/// Parses the version out of a tag such as `zbcli-1.2.3` or `zbcli-v1.2.3`
+#[must_use]
+pub fn tag_version(tag: &str, tag_prefix: &str) -> Option<Version> {
+    let version = tag.strip_prefix(tag_prefix)?.strip_prefix('-')?;
+    Version::parse(version.strip_prefix('v').unwrap_or(version)).ok()
+}
+
+/// Whether `release` is a pre-release, either by the source's flag or by a semver pre-release
+/// identifier in its tag such as `zbcli-1.2.0-rc.1`
+#[must_use]
+pub fn is_prerelease(release: &Release, tag_prefix: &str) -> bool {
+    release.prerelease
+        || tag_version(&release.tag, tag_prefix).is_some_and(|version| !version.pre.is_empty())
+}
 
 /// `tag_prefix`: "zbcli" in "zbcli-1.1.0"
+///
+/// Without `zb_version`, returns the `n_items` most recent releases. A constraint returns every
+/// matching release, newest version first. Pre-releases are left out unless
+/// `include_prereleases` is set, or they are asked for by tag.
 pub async fn list(
+    source: &dyn ReleaseSource,
     tag_prefix: &str,
-    zb_version: &Option<String>,
+    zb_version: Option<&VersionSpec>,
     n_items: u8,
+    include_prereleases: bool,
 ) -> Result<Vec<Release>> {
-    let github_instance = octocrab::instance();
-    #[cfg(feature = "zbcli-devel")]
-    let repo = github_instance.repos("zymbit-applications", "zb-bin-devel");
-    #[cfg(not(feature = "zbcli-devel"))]
-    let repo = github_instance.repos("zymbit-applications", "zb-bin");
-
-    let releases = repo.releases();
-
-    let release_list = if let Some(version) = zb_version {
-        let release = if version.to_lowercase().eq("latest") {
-            releases
-                .get_latest()
-                .await
-                .context("Failed to get latest release")?
-        } else {
-            let version_tag = encode(version).into_owned();
-            releases
-                .get_by_tag(version_tag.as_str())
-                .await
-                .context(format!("Failed to get release tagged with '{version}'.\n"))?
-        };
-        std::iter::once(release).collect()
-    } else {
-        let mut count = 0usize;
-        let mut page = 1u32;
-        let mut accumulate = Vec::new();
-        while count < (n_items as usize) {
-            accumulate.extend(
-                releases
-                    .list()
-                    .per_page(n_items)
-                    .page(page)
-                    .send()
-                    .await
-                    .map(|page|
-                        page.into_iter()
-                    ).or_else(|e|
-                        // don't bail if we get no results back unless we haven't accumulated any
-                        // from previous pages
-                        if accumulate.is_empty() {
-                            Err(e)
-                        } else {
-                            Ok(Vec::new().into_iter())
-                        })
-                    .context("Failed to get latest releases")?
-                    .filter(|release| release.tag_name.starts_with(tag_prefix)
-                                && !release.tag_name.contains("rc")), // exclude release candidates
-            );
-            if count == accumulate.len() {
-                break;
-            }
-            count = accumulate.len();
-            page += 1;
+    let release_list = match zb_version {
+        Some(VersionSpec::Latest) => latest(source, tag_prefix, include_prereleases).await?,
+        Some(VersionSpec::Tag(tag)) => vec![source
+            .by_tag(tag)
+            .await
+            .context(format!("Failed to get release tagged with '{tag}'.\n"))?],
+        Some(VersionSpec::Constraint(req)) => {
+            matching(source, tag_prefix, req, include_prereleases).await?
         }
-        accumulate.truncate(n_items as usize);
-        accumulate
+        None => recent(source, tag_prefix, n_items, include_prereleases).await?,
     };
 
     Ok(release_list)
 }
+
+/// The newest release of `tag_prefix`. The source's own latest release is used when it is a
+/// stable `tag_prefix` release; otherwise, e.g. when the newest release is a pre-release or the
+/// `installer` release, the release list is searched instead.
+async fn latest(
+    source: &dyn ReleaseSource,
+    tag_prefix: &str,
+    include_prereleases: bool,
+) -> Result<Vec<Release>> {
+    if !include_prereleases {
+        let release = source
+            .latest()
+            .await
+            .context("Failed to get latest release")?;
+        if release.tag.starts_with(tag_prefix) && !is_prerelease(&release, tag_prefix) {
+            return Ok(vec![release]);
+        }
+    }
+
+    let releases = recent(source, tag_prefix, 1, include_prereleases).await?;
+    if releases.is_empty() {
+        bail!("no {tag_prefix} release found");
+    }
+
+    Ok(releases)
+}
+
+/// The `n_items` most recent releases of `tag_prefix`, newest first
+async fn recent(
+    source: &dyn ReleaseSource,
+    tag_prefix: &str,
+    n_items: u8,
+    include_prereleases: bool,
+) -> Result<Vec<Release>> {
+    // pages are filtered, so fetch more than asked for to keep the number of requests down
+    let per_page = n_items.max(10);
+    let mut page = 1u32;
+    let mut accumulate = Vec::new();
+    while accumulate.len() < (n_items as usize) {
+        let releases = source
+            .list(page, per_page)
+            .await
+            .or_else(|e|
+                // don't bail if we get no results back unless we haven't accumulated any
+                // from previous pages
+                if accumulate.is_empty() {
+                    Err(e)
+                } else {
+                    Ok(Vec::new())
+                })
+            .context("Failed to get latest releases")?;
+        // a page with nothing for `tag_prefix` isn't the end; only a short page is
+        let last_page = releases.len() < usize::from(per_page);
+        accumulate.extend(releases.into_iter().filter(|release| {
+            release.tag.starts_with(tag_prefix)
+                && (include_prereleases || !is_prerelease(release, tag_prefix))
+        }));
+        if last_page {
+            break;
+        }
+        page += 1;
+    }
+    accumulate.truncate(n_items as usize);
+
+    Ok(accumulate)
+}
+
+/// Every release of `tag_prefix` whose version satisfies `req`, newest version first.
+///
+/// With `include_prereleases`, a pre-release matches if the version it leads up to does, so
+/// `=1.2` takes in `1.2.4-rc.1` as well.
+async fn matching(
+    source: &dyn ReleaseSource,
+    tag_prefix: &str,
+    req: &VersionReq,
+    include_prereleases: bool,
+) -> Result<Vec<Release>> {
+    const PER_PAGE: u8 = 100;
+
+    let mut matches = Vec::new();
+    let mut page = 1u32;
+    loop {
+        let releases = source
+            .list(page, PER_PAGE)
+            .await
+            .context("Failed to get releases")?;
+        let last_page = releases.len() < usize::from(PER_PAGE);
+
+        matches.extend(releases.into_iter().filter_map(|release| {
+            let version = tag_version(&release.tag, tag_prefix)?;
+            let matches = if include_prereleases {
+                req.matches(&Version {
+                    pre: Prerelease::EMPTY,
+                    ..version.clone()
+                })
+            } else {
+                !is_prerelease(&release, tag_prefix) && req.matches(&version)
+            };
+            matches.then_some((version, release))
+        }));
+
+        if last_page {
+            break;
+        }
+        page += 1;
+    }
+
+    if matches.is_empty() {
+        bail!("no {tag_prefix} release matches '{req}'");
+    }
+    matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    Ok(matches.into_iter().map(|(_, release)| release).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+
+    /// Releases held in memory, newest first
+    struct FakeSource(Vec<Release>);
+
+    #[async_trait]
+    impl ReleaseSource for FakeSource {
+        fn describe(&self) -> String {
+            "fake".to_string()
+        }
+
+        fn channel(&self) -> String {
+            self.describe()
+        }
+
+        async fn latest(&self) -> Result<Release> {
+            match self.0.iter().find(|release| !release.prerelease) {
+                Some(release) => Ok(release.clone()),
+                None => bail!("no releases"),
+            }
+        }
+
+        async fn by_tag(&self, tag: &str) -> Result<Release> {
+            match self.0.iter().find(|release| release.tag == tag) {
+                Some(release) => Ok(release.clone()),
+                None => bail!("no release tagged '{tag}'"),
+            }
+        }
+
+        async fn list(&self, page: u32, per_page: u8) -> Result<Vec<Release>> {
+            let skip = (page.saturating_sub(1) as usize) * usize::from(per_page);
+            Ok(self
+                .0
+                .iter()
+                .skip(skip)
+                .take(usize::from(per_page))
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn release(tag: &str, prerelease: bool) -> Release {
+        Release {
+            tag: tag.to_string(),
+            prerelease,
+            assets: Vec::new(),
+        }
+    }
+
+    fn constraint(spec: &str) -> VersionReq {
+        match spec.parse().unwrap() {
+            VersionSpec::Constraint(req) => req,
+            other => panic!("'{spec}' parsed as {other:?}"),
+        }
+    }
+
+    fn tags(releases: &[Release]) -> Vec<&str> {
+        releases
+            .iter()
+            .map(|release| release.tag.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn bare_version_pins_named_components() {
+        let req = constraint("1.2");
+        assert_eq!(req.to_string(), "=1.2");
+        assert!(req.matches(&Version::new(1, 2, 0)));
+        assert!(req.matches(&Version::new(1, 2, 9)));
+        assert!(!req.matches(&Version::new(1, 3, 0)));
+
+        assert_eq!(constraint("1.2.3").to_string(), "=1.2.3");
+    }
+
+    #[test]
+    fn parses_constraints() {
+        let tilde = constraint("~1.2");
+        assert!(tilde.matches(&Version::new(1, 2, 7)));
+        assert!(!tilde.matches(&Version::new(1, 3, 0)));
+
+        let range = constraint(">=1.1,<2");
+        assert!(range.matches(&Version::new(1, 1, 0)));
+        assert!(range.matches(&Version::new(1, 9, 3)));
+        assert!(!range.matches(&Version::new(2, 0, 0)));
+    }
+
+    #[test]
+    fn parses_latest_and_tags() {
+        assert!(matches!("latest".parse(), Ok(VersionSpec::Latest)));
+        assert!(matches!(" LATEST ".parse(), Ok(VersionSpec::Latest)));
+        assert!(matches!("zbcli-1.2.3".parse(), Ok(VersionSpec::Tag(tag)) if tag == "zbcli-1.2.3"));
+    }
+
+    #[test]
+    fn rejects_invalid_constraint() {
+        let error = ">=1.x.y".parse::<VersionSpec>().unwrap_err();
+        assert_eq!(error.to_string(), "invalid version constraint '>=1.x.y'");
+        assert!("1.2.3.4".parse::<VersionSpec>().is_err());
+    }
+
+    #[test]
+    fn reads_version_from_tag() {
+        assert_eq!(
+            tag_version("zbcli-1.2.3", "zbcli"),
+            Some(Version::new(1, 2, 3))
+        );
+        assert_eq!(
+            tag_version("zbcli-v1.2.3", "zbcli"),
+            Some(Version::new(1, 2, 3))
+        );
+        assert_eq!(tag_version("installer", "zbcli"), None);
+        assert_eq!(tag_version("zbclix-1.2.3", "zbcli"), None);
+    }
+
+    #[tokio::test]
+    async fn picks_newest_match_first() {
+        // listed in publishing order, which isn't version order
+        let source = FakeSource(vec![
+            release("zbcli-1.1.9", false),
+            release("zbcli-2.0.0", false),
+            release("zbcli-1.2.10", false),
+            release("installer", false),
+            release("zbcli-1.2.2", false),
+            release("zbcli-1.0.0", false),
+        ]);
+
+        let spec = "~1.2".parse().unwrap();
+        let releases = list(&source, "zbcli", Some(&spec), 10, false)
+            .await
+            .unwrap();
+        assert_eq!(tags(&releases), ["zbcli-1.2.10", "zbcli-1.2.2"]);
+
+        let spec = ">=1.1,<2".parse().unwrap();
+        let releases = list(&source, "zbcli", Some(&spec), 10, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            tags(&releases),
+            ["zbcli-1.2.10", "zbcli-1.2.2", "zbcli-1.1.9"]
+        );
+
+        let spec = "~3".parse().unwrap();
+        assert!(list(&source, "zbcli", Some(&spec), 10, false)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn classifies_prereleases() {
+        assert!(is_prerelease(&release("zbcli-1.3.0-rc.1", false), "zbcli"));
+        assert!(is_prerelease(&release("zbcli-1.3.0-beta", false), "zbcli"));
+        assert!(is_prerelease(
+            &release("zbcli-1.3.0-alpha.2", false),
+            "zbcli"
+        ));
+        assert!(is_prerelease(&release("zbcli-1.3.0", true), "zbcli"));
+
+        assert!(!is_prerelease(&release("zbcli-1.3.0", false), "zbcli"));
+        // contains "rc", but only as build metadata
+        assert!(!is_prerelease(
+            &release("zbcli-1.3.0+source", false),
+            "zbcli"
+        ));
+    }
+
+    #[tokio::test]
+    async fn includes_prereleases_of_matching_versions_on_request() {
+        let source = FakeSource(vec![
+            release("zbcli-1.3.0-rc.1", false),
+            release("zbcli-1.2.4-rc.1", false),
+            release("zbcli-1.2.3+source", false),
+            release("zbcli-1.2.3", false),
+        ]);
+        let spec = "=1.2".parse().unwrap();
+
+        let releases = list(&source, "zbcli", Some(&spec), 10, true).await.unwrap();
+        assert_eq!(
+            tags(&releases),
+            ["zbcli-1.2.4-rc.1", "zbcli-1.2.3+source", "zbcli-1.2.3"]
+        );
+
+        let releases = list(&source, "zbcli", Some(&spec), 10, false)
+            .await
+            .unwrap();
+        assert_eq!(tags(&releases), ["zbcli-1.2.3+source", "zbcli-1.2.3"]);
+    }
+
+    #[tokio::test]
+    async fn latest_skips_prereleases_and_other_tags() {
+        // the source takes the unflagged pre-release for its latest release
+        let source = FakeSource(vec![
+            release("zbcli-1.3.0-rc.1", false),
+            release("installer", false),
+            release("zbcli-1.2.3", false),
+        ]);
+
+        let releases = list(&source, "zbcli", Some(&VersionSpec::Latest), 10, false)
+            .await
+            .unwrap();
+        assert_eq!(tags(&releases), ["zbcli-1.2.3"]);
+
+        let releases = list(&source, "zbcli", Some(&VersionSpec::Latest), 10, true)
+            .await
+            .unwrap();
+        assert_eq!(tags(&releases), ["zbcli-1.3.0-rc.1"]);
+    }
+
+    #[tokio::test]
+    async fn recent_releases_leave_out_prereleases() {
+        let source = FakeSource(vec![
+            release("zbcli-1.3.0-rc.1", false),
+            release("zbcli-1.2.3", false),
+            release("zbcli-1.2.2", true),
+            release("zbcli-1.2.1", false),
+        ]);
+
+        let releases = list(&source, "zbcli", None, 2, false).await.unwrap();
+        assert_eq!(tags(&releases), ["zbcli-1.2.3", "zbcli-1.2.1"]);
+
+        let releases = list(&source, "zbcli", None, 2, true).await.unwrap();
+        assert_eq!(tags(&releases), ["zbcli-1.3.0-rc.1", "zbcli-1.2.3"]);
+    }
+}
@@ -1,3 +1,32 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Which key `zbcli` signs with
+#[derive(derive_more::Display, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Signing {
+    #[display(fmt = "software")]
+    Software,
+
+    /// Signing with the key held by an SCM or HSM6
+    #[display(fmt = "hardware")]
+    Hardware,
+}
+
+impl FromStr for Signing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "software" => Ok(Signing::Software),
+            "hardware" => Ok(Signing::Hardware),
+            _ => bail!("unknown signing '{s}', expected 'software' or 'hardware'"),
+        }
+    }
+}
+
 #[derive(derive_more::Display, PartialEq)]
 pub enum ZbcliAsset {
     #[display(fmt = "zbcli-rpizero2w")]
@@ -18,3 +47,15 @@ pub enum ZbcliAsset {
     #[display(fmt = "zbcli-rpi5-hardware")]
     Rpi5Hardware,
 }
+
+impl ZbcliAsset {
+    #[must_use]
+    pub fn signing(&self) -> Signing {
+        match self {
+            ZbcliAsset::Rpi0Hardware | ZbcliAsset::Rpi4Hardware | ZbcliAsset::Rpi5Hardware => {
+                Signing::Hardware
+            }
+            ZbcliAsset::Rpi0 | ZbcliAsset::Rpi4 | ZbcliAsset::Rpi5 => Signing::Software,
+        }
+    }
+}